@@ -0,0 +1,325 @@
+use super::{AlertEvent, AlertStatus, Notifier};
+use crate::config::AlertmanagerConfig;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::debug;
+
+const ALERT_NAME: &str = "NodeTemperatureHigh";
+
+// Firing alerts are valid for this many resend intervals, so a few failed deliveries don't resolve them
+const RESEND_GRACE: u32 = 4;
+
+/// Body element of `POST /api/v2/alerts`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PostableAlert {
+    labels: BTreeMap<String, String>,
+    annotations: BTreeMap<String, String>,
+    starts_at: DateTime<Utc>,
+    ends_at: DateTime<Utc>,
+    #[serde(rename = "generatorURL", skip_serializing_if = "Option::is_none")]
+    generator_url: Option<String>,
+}
+
+// Label set last sent as firing for a node, so changes can resolve the previous alert
+struct ActiveAlert {
+    labels: BTreeMap<String, String>,
+    fingerprint: String,
+    starts_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct Delivery {
+    active: HashMap<String, ActiveAlert>,
+    last_sent: Option<Instant>,
+}
+
+/// Pushes alerts to an Alertmanager and re-sends firing ones before they time out.
+pub struct AlertmanagerNotifier {
+    name: String,
+    endpoint: String,
+    labels: BTreeMap<String, String>,
+    generator_url: Option<String>,
+    resend_interval: Duration,
+    client: Client,
+    timeout: Duration,
+    delivery: Mutex<Delivery>,
+}
+
+impl AlertmanagerNotifier {
+    pub fn new(config: &AlertmanagerConfig, client: Client, timeout: Duration) -> anyhow::Result<Self> {
+        Ok(Self {
+            name: config.name.clone(),
+            endpoint: format!("{}/api/v2/alerts", config.url.trim_end_matches('/')),
+            labels: config.labels.clone(),
+            generator_url: config.generator_url.clone(),
+            resend_interval: config.resend_interval()?,
+            client,
+            timeout,
+            delivery: Mutex::default(),
+        })
+    }
+
+    fn labels(&self, event: &AlertEvent) -> BTreeMap<String, String> {
+        let mut labels = self.labels.clone();
+        labels.insert("alertname".to_string(), ALERT_NAME.to_string());
+        labels.insert("node".to_string(), event.node.clone());
+        // A federated cluster replaces a statically configured cluster label
+        if let Some(cluster) = &event.cluster {
+            labels.insert("cluster".to_string(), cluster.clone());
+        }
+        labels.insert("severity".to_string(), event.severity.to_string());
+        if let Some(sensor) = &event.sensor {
+            labels.insert("chip".to_string(), sensor.chip.clone());
+            labels.insert("sensor".to_string(), sensor.sensor.clone());
+        }
+        labels
+    }
+
+    fn alert(&self, event: &AlertEvent, labels: BTreeMap<String, String>, now: DateTime<Utc>) -> PostableAlert {
+        let ends_at = match event.status {
+            AlertStatus::Firing => now + self.resend_interval * RESEND_GRACE,
+            AlertStatus::Resolved => event.ends_at.unwrap_or(now),
+        };
+        PostableAlert {
+            labels,
+            annotations: annotations(event),
+            starts_at: event.starts_at,
+            ends_at,
+            generator_url: self.generator_url.clone(),
+        }
+    }
+
+    async fn post(&self, alerts: &[PostableAlert]) -> anyhow::Result<()> {
+        self.client
+            .post(&self.endpoint)
+            .timeout(self.timeout)
+            .json(alerts)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notifier for AlertmanagerNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, events: &[AlertEvent]) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let mut delivery = self.delivery.lock().await;
+        let mut alerts = Vec::with_capacity(events.len());
+        for event in events {
+            let labels = self.labels(event);
+            let fingerprint = fingerprint(&labels);
+            // A severity change is a different label set, so the old alert has to be resolved explicitly
+            if let Some(previous) = delivery.active.remove(&event.key()) {
+                if previous.fingerprint != fingerprint {
+                    debug!("Resolving alert {} for {} after a label change", previous.fingerprint, event.key());
+                    alerts.push(PostableAlert {
+                        labels: previous.labels,
+                        annotations: BTreeMap::new(),
+                        starts_at: previous.starts_at,
+                        ends_at: now,
+                        generator_url: self.generator_url.clone(),
+                    });
+                }
+            }
+            if event.status == AlertStatus::Firing {
+                delivery.active.insert(
+                    event.key(),
+                    ActiveAlert { labels: labels.clone(), fingerprint, starts_at: event.starts_at },
+                );
+            }
+            alerts.push(self.alert(event, labels, now));
+        }
+        if alerts.is_empty() {
+            return Ok(());
+        }
+        self.post(&alerts).await?;
+        delivery.last_sent = Some(Instant::now());
+        Ok(())
+    }
+
+    async fn refresh(&self, firing: &[AlertEvent]) -> anyhow::Result<()> {
+        let mut delivery = self.delivery.lock().await;
+        if firing.is_empty() || delivery.last_sent.is_some_and(|sent| sent.elapsed() < self.resend_interval) {
+            return Ok(());
+        }
+        let now = Utc::now();
+        let alerts: Vec<PostableAlert> =
+            firing.iter().map(|event| self.alert(event, self.labels(event), now)).collect();
+        self.post(&alerts).await?;
+        delivery.last_sent = Some(Instant::now());
+        Ok(())
+    }
+}
+
+fn annotations(event: &AlertEvent) -> BTreeMap<String, String> {
+    let mut annotations = BTreeMap::new();
+    let source = match &event.sensor {
+        Some(sensor) => match &sensor.label {
+            Some(label) => format!("{} ({}/{})", label, sensor.chip, sensor.sensor),
+            None => format!("{}/{}", sensor.chip, sensor.sensor),
+        },
+        None => "hottest sensor".to_string(),
+    };
+    let summary = match event.threshold {
+        Some(threshold) => format!(
+            "{} is {} at {:.1}°C (threshold {:.1}°C)",
+            event.key(), event.severity, event.temperature, threshold
+        ),
+        None => format!("{} is {} at {:.1}°C", event.key(), event.severity, event.temperature),
+    };
+    annotations.insert("summary".to_string(), summary);
+    let mut description = format!("{} on {} reads {:.1}°C.", source, event.key(), event.temperature);
+    let peaks: Vec<String> = [("hour", event.hourly_temperature), ("day", event.daily_temperature)]
+        .into_iter()
+        .filter_map(|(period, peak)| Some(format!("{:.1}°C over the last {}", peak?, period)))
+        .collect();
+    if !peaks.is_empty() {
+        description.push_str(&format!(" The node peaked at {}.", peaks.join(" and ")));
+    }
+    annotations.insert("description".to_string(), description);
+    annotations.insert("temperature".to_string(), format!("{:.1}", event.temperature));
+    if let Some(hourly) = event.hourly_temperature {
+        annotations.insert("hourly_temperature".to_string(), format!("{:.1}", hourly));
+    }
+    if let Some(daily) = event.daily_temperature {
+        annotations.insert("daily_temperature".to_string(), format!("{:.1}", daily));
+    }
+    if let Some(threshold) = event.threshold {
+        annotations.insert("threshold".to_string(), format!("{:.1}", threshold));
+    }
+    if let Some(label) = event.sensor.as_ref().and_then(|sensor| sensor.label.as_ref()) {
+        annotations.insert("sensor_label".to_string(), label.clone());
+    }
+    annotations
+}
+
+/// Fingerprint of a label set, computed the way Alertmanager does (FNV-1a over sorted names and values).
+fn fingerprint(labels: &BTreeMap<String, String>) -> String {
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    const SEPARATOR: u8 = 0xff;
+
+    let mut hash = OFFSET;
+    let mut add = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(PRIME);
+        }
+    };
+    for (name, value) in labels {
+        add(name.as_bytes());
+        add(&[SEPARATOR]);
+        add(value.as_bytes());
+        add(&[SEPARATOR]);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerting::tests::{at, measurement};
+    use crate::alerting::AlertEngine;
+    use axum::{extract::State, routing::post, Json, Router};
+    use std::sync::Arc;
+
+    type Received = Arc<Mutex<Vec<Vec<serde_json::Value>>>>;
+
+    async fn alertmanager() -> (AlertmanagerNotifier, Received) {
+        let received: Received = Arc::default();
+        let receiver = Router::new()
+            .route(
+                "/api/v2/alerts",
+                post(|State(received): State<Received>, Json(body): Json<Vec<serde_json::Value>>| async move {
+                    received.lock().await.push(body);
+                }),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+        let config = AlertmanagerConfig {
+            name: "test".to_string(),
+            url: format!("http://{}/", addr),
+            resend_interval: "1m".to_string(),
+            labels: BTreeMap::from([("cluster".to_string(), "homelab".to_string())]),
+            generator_url: None,
+        };
+        let notifier = AlertmanagerNotifier::new(&config, Client::new(), Duration::from_secs(5)).unwrap();
+        (notifier, received)
+    }
+
+    #[tokio::test]
+    async fn escalation_resolves_previous_label_set() {
+        let (notifier, received) = alertmanager().await;
+        let mut engine = AlertEngine::new(Duration::ZERO, 2.0);
+
+        notifier.notify(&engine.evaluate(&[measurement("blade001", 75.0)], at(0))).await.unwrap();
+        notifier.notify(&engine.evaluate(&[measurement("blade001", 90.0)], at(1))).await.unwrap();
+
+        let received = received.lock().await;
+        assert_eq!(received.len(), 2);
+        let first = &received[0][0];
+        assert_eq!(first["labels"]["alertname"], ALERT_NAME);
+        assert_eq!(first["labels"]["node"], "blade001");
+        assert_eq!(first["labels"]["sensor"], "temp1");
+        assert_eq!(first["labels"]["severity"], "warning");
+        assert_eq!(first["labels"]["cluster"], "homelab");
+        assert_eq!(first["annotations"]["threshold"], "70.0");
+
+        let (resolved, firing) = (&received[1][0], &received[1][1]);
+        assert_eq!(resolved["labels"], first["labels"]);
+        assert_eq!(resolved["startsAt"], first["startsAt"]);
+        assert_eq!(firing["labels"]["severity"], "critical");
+        assert!(resolved["endsAt"].as_str() < firing["endsAt"].as_str());
+    }
+
+    #[tokio::test]
+    async fn firing_alerts_are_resent_after_interval() {
+        let (notifier, received) = alertmanager().await;
+        let mut engine = AlertEngine::new(Duration::ZERO, 2.0);
+
+        notifier.notify(&engine.evaluate(&[measurement("blade001", 90.0)], at(0))).await.unwrap();
+        // Just delivered, nothing to re-send yet
+        notifier.refresh(&engine.firing()).await.unwrap();
+        assert_eq!(received.lock().await.len(), 1);
+
+        notifier.delivery.lock().await.last_sent = Some(Instant::now() - Duration::from_secs(61));
+        notifier.refresh(&engine.firing()).await.unwrap();
+
+        let received = received.lock().await;
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[1][0]["labels"], received[0][0]["labels"]);
+        assert_eq!(received[1][0]["startsAt"], received[0][0]["startsAt"]);
+    }
+
+    #[test]
+    fn fingerprint_depends_only_on_labels() {
+        let labels = |severity: &str| {
+            BTreeMap::from([
+                ("alertname".to_string(), ALERT_NAME.to_string()),
+                ("node".to_string(), "blade001".to_string()),
+                ("severity".to_string(), severity.to_string()),
+            ])
+        };
+
+        assert_eq!(fingerprint(&labels("warning")), fingerprint(&labels("warning")));
+        assert_ne!(fingerprint(&labels("warning")), fingerprint(&labels("critical")));
+        // Empty label set hashes to the FNV offset basis
+        assert_eq!(fingerprint(&BTreeMap::new()), "cbf29ce484222325");
+    }
+}
@@ -0,0 +1,597 @@
+mod alertmanager;
+
+use crate::config::{Config, WebhookConfig};
+use crate::state::AppState;
+use crate::thresholds::{worst_judged, Limits, Status};
+use crate::{fetch_temperatures, Detail, QueryParams, TemperatureMeasurement};
+use alertmanager::AlertmanagerNotifier;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertStatus {
+    Firing,
+    Resolved,
+}
+
+/// A notification about one node starting, changing severity or stopping to overheat.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AlertEvent {
+    pub status: AlertStatus,
+    pub node: String,
+    // Federated cluster the node belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<String>,
+    pub severity: Status,
+    // Set when a firing alert changed severity, e.g. escalated from warning to critical
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_severity: Option<Status>,
+    // Hottest sensor that triggered the alert
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sensor: Option<SensorRef>,
+    pub temperature: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f64>,
+    // Node maxima over the hourly and daily windows, for context
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hourly_temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_temperature: Option<f64>,
+    pub starts_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+impl AlertEvent {
+    /// Identifies the alerting node across clusters, like `TemperatureMeasurement::key`.
+    pub fn key(&self) -> String {
+        match &self.cluster {
+            Some(cluster) => format!("{}/{}", cluster, self.node),
+            None => self.node.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SensorRef {
+    pub chip: String,
+    pub sensor: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    // Above a threshold, waiting for the pending duration to pass
+    Pending,
+    Firing,
+}
+
+#[derive(Debug, Clone)]
+struct NodeAlert {
+    node: String,
+    cluster: Option<String>,
+    phase: Phase,
+    severity: Status,
+    // When the node first crossed a threshold
+    since: DateTime<Utc>,
+    sensor: Option<SensorRef>,
+    temperature: f64,
+    threshold: Option<f64>,
+    hourly_temperature: Option<f64>,
+    daily_temperature: Option<f64>,
+}
+
+// Current condition of a node: `raw` uses the thresholds as configured, `relaxed` lowers them by the hysteresis
+struct Assessment {
+    raw: Status,
+    relaxed: Status,
+    sensor: Option<SensorRef>,
+    temperature: f64,
+    threshold: Option<f64>,
+    hourly_temperature: Option<f64>,
+    daily_temperature: Option<f64>,
+}
+
+/// Tracks alert state per node across evaluations.
+pub struct AlertEngine {
+    pending: chrono::Duration,
+    hysteresis: f64,
+    // Keyed by `TemperatureMeasurement::key`, so equally named nodes of different clusters are kept apart
+    alerts: HashMap<String, NodeAlert>,
+}
+
+impl AlertEngine {
+    pub fn new(pending: Duration, hysteresis: f64) -> Self {
+        Self {
+            pending: chrono::Duration::from_std(pending).unwrap_or(chrono::Duration::MAX),
+            hysteresis,
+            alerts: HashMap::new(),
+        }
+    }
+
+    /// Applies one snapshot and returns the notifications it caused. Nodes without a usable reading keep their
+    /// current state, so a scrape gap neither fires nor resolves anything.
+    pub fn evaluate(&mut self, measurements: &[TemperatureMeasurement], now: DateTime<Utc>) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+
+        for measurement in measurements {
+            let Some(assessment) = assess(measurement, self.hysteresis) else {
+                continue;
+            };
+            if assessment.raw == Status::Unknown {
+                continue;
+            }
+            let key = measurement.key();
+
+            let Some(alert) = self.alerts.get_mut(&key) else {
+                if is_alerting(assessment.raw) {
+                    let alert = NodeAlert {
+                        node: measurement.node.clone(),
+                        cluster: measurement.cluster.clone(),
+                        phase: Phase::Pending,
+                        severity: assessment.raw,
+                        since: now,
+                        sensor: assessment.sensor,
+                        temperature: assessment.temperature,
+                        threshold: assessment.threshold,
+                        hourly_temperature: assessment.hourly_temperature,
+                        daily_temperature: assessment.daily_temperature,
+                    };
+                    let alert = self.alerts.entry(key).or_insert(alert);
+                    if self.pending.is_zero() {
+                        alert.phase = Phase::Firing;
+                        events.push(alert.event(AlertStatus::Firing, None, None));
+                    }
+                }
+                continue;
+            };
+
+            match alert.phase {
+                Phase::Pending if !is_alerting(assessment.raw) => {
+                    self.alerts.remove(&key);
+                }
+                Phase::Pending => {
+                    alert.update(&assessment, assessment.raw);
+                    if now - alert.since >= self.pending {
+                        alert.phase = Phase::Firing;
+                        events.push(alert.event(AlertStatus::Firing, None, None));
+                    }
+                }
+                Phase::Firing if !is_alerting(assessment.relaxed) => {
+                    alert.refresh(&assessment);
+                    events.push(alert.event(AlertStatus::Resolved, None, Some(now)));
+                    self.alerts.remove(&key);
+                }
+                Phase::Firing => {
+                    // Escalate immediately, downgrade only once below the hysteresis band
+                    let severity = if assessment.raw > alert.severity {
+                        assessment.raw
+                    } else if assessment.relaxed < alert.severity {
+                        assessment.relaxed
+                    } else {
+                        alert.severity
+                    };
+                    if severity != alert.severity {
+                        let previous = alert.severity;
+                        alert.update(&assessment, severity);
+                        events.push(alert.event(AlertStatus::Firing, Some(previous), None));
+                    } else {
+                        alert.refresh(&assessment);
+                    }
+                }
+            }
+        }
+
+        events
+    }
+
+    /// Events for every currently firing alert, for notifiers that expect periodic re-sends.
+    pub fn firing(&self) -> Vec<AlertEvent> {
+        let mut events: Vec<AlertEvent> = self
+            .alerts
+            .iter()
+            .filter(|(_, alert)| alert.phase == Phase::Firing)
+            .map(|(_, alert)| alert.event(AlertStatus::Firing, None, None))
+            .collect();
+        events.sort_by(|a, b| (&a.cluster, &a.node).cmp(&(&b.cluster, &b.node)));
+        events
+    }
+}
+
+impl NodeAlert {
+    fn update(&mut self, assessment: &Assessment, severity: Status) {
+        self.severity = severity;
+        self.sensor = assessment.sensor.clone();
+        self.threshold = assessment.threshold;
+        self.refresh(assessment);
+    }
+
+    // Latest readings, without touching severity or the reported sensor
+    fn refresh(&mut self, assessment: &Assessment) {
+        self.temperature = assessment.temperature;
+        self.hourly_temperature = assessment.hourly_temperature;
+        self.daily_temperature = assessment.daily_temperature;
+    }
+
+    fn event(
+        &self,
+        status: AlertStatus,
+        previous_severity: Option<Status>,
+        ends_at: Option<DateTime<Utc>>,
+    ) -> AlertEvent {
+        AlertEvent {
+            status,
+            node: self.node.clone(),
+            cluster: self.cluster.clone(),
+            severity: self.severity,
+            previous_severity,
+            sensor: self.sensor.clone(),
+            temperature: self.temperature,
+            threshold: self.threshold,
+            hourly_temperature: self.hourly_temperature,
+            daily_temperature: self.daily_temperature,
+            starts_at: self.since,
+            ends_at,
+        }
+    }
+}
+
+fn is_alerting(status: Status) -> bool {
+    matches!(status, Status::Warning | Status::Critical)
+}
+
+// None without a current (minutely) reading to judge, or when no sensor of the node has limits
+fn assess(measurement: &TemperatureMeasurement, hysteresis: f64) -> Option<Assessment> {
+    let Some(sensors) = measurement.sensors.as_ref().filter(|sensors| !sensors.is_empty()) else {
+        return Some(Assessment {
+            raw: measurement.status,
+            relaxed: measurement.status,
+            sensor: None,
+            temperature: measurement.minutely_temperature?,
+            threshold: None,
+            hourly_temperature: measurement.hourly_temperature,
+            daily_temperature: measurement.daily_temperature,
+        });
+    };
+
+    let relaxed = worst_judged(sensors.iter().map(|sensor| {
+        let lowered = Limits::new(
+            sensor.thresholds.warning.map(|warning| warning - hysteresis),
+            sensor.thresholds.critical.map(|critical| critical - hysteresis),
+        );
+        sensor.minutely_temperature.map_or(Status::Unknown, |value| lowered.status(value))
+    }));
+
+    // The worst sensor, hottest first among equally bad ones, is the one reported. Sensors without limits are left
+    // out like for the node status, or a cooled node carrying one would never resolve.
+    let worst = sensors.iter().filter(|sensor| sensor.status != Status::Unknown).max_by(|a, b| {
+        a.status
+            .cmp(&b.status)
+            .then(a.minutely_temperature.partial_cmp(&b.minutely_temperature).unwrap_or(Ordering::Equal))
+    })?;
+
+    Some(Assessment {
+        raw: worst.status,
+        relaxed,
+        sensor: Some(SensorRef {
+            chip: worst.chip.clone(),
+            sensor: worst.sensor.clone(),
+            label: worst.label.clone(),
+        }),
+        temperature: worst.minutely_temperature?,
+        threshold: match worst.status {
+            Status::Critical => worst.thresholds.critical,
+            Status::Warning => worst.thresholds.warning,
+            _ => None,
+        },
+        hourly_temperature: measurement.hourly_temperature,
+        daily_temperature: measurement.daily_temperature,
+    })
+}
+
+/// Destination for alert notifications.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Delivers state changes produced by one evaluation.
+    async fn notify(&self, events: &[AlertEvent]) -> anyhow::Result<()>;
+
+    /// Called after every evaluation with all firing alerts, for receivers that need periodic re-sends.
+    async fn refresh(&self, _firing: &[AlertEvent]) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Posts every event as a JSON object to a generic HTTP endpoint.
+pub struct WebhookNotifier {
+    name: String,
+    url: String,
+    headers: BTreeMap<String, String>,
+    client: Client,
+    timeout: Duration,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: &WebhookConfig, client: Client, timeout: Duration) -> Self {
+        Self {
+            name: config.name.clone(),
+            url: config.url.clone(),
+            headers: config.headers.clone(),
+            client,
+            timeout,
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, events: &[AlertEvent]) -> anyhow::Result<()> {
+        for event in events {
+            let mut request = self.client.post(&self.url).timeout(self.timeout).json(event);
+            for (name, value) in &self.headers {
+                request = request.header(name, value);
+            }
+            request.send().await?.error_for_status()?;
+        }
+        Ok(())
+    }
+}
+
+fn notifiers_from_config(config: &Config, client: &Client) -> anyhow::Result<Vec<Arc<dyn Notifier>>> {
+    let timeout = config.webhook_timeout()?;
+    let mut notifiers: Vec<Arc<dyn Notifier>> = Vec::new();
+    for webhook in &config.alerting.webhooks {
+        notifiers.push(Arc::new(WebhookNotifier::new(webhook, client.clone(), timeout)));
+    }
+    for alertmanager in &config.alerting.alertmanagers {
+        notifiers.push(Arc::new(AlertmanagerNotifier::new(alertmanager, client.clone(), timeout)?));
+    }
+    Ok(notifiers)
+}
+
+async fn dispatch(notifiers: &[Arc<dyn Notifier>], events: &[AlertEvent]) {
+    if events.is_empty() {
+        return;
+    }
+    let deliveries = notifiers.iter().map(|notifier| async move {
+        if let Err(e) = notifier.notify(events).await {
+            warn!("Failed to deliver {} alert event(s) to {}: {}", events.len(), notifier.name(), e);
+        }
+    });
+    futures::future::join_all(deliveries).await;
+}
+
+async fn refresh(notifiers: &[Arc<dyn Notifier>], firing: &[AlertEvent]) {
+    let refreshes = notifiers.iter().map(|notifier| async move {
+        if let Err(e) = notifier.refresh(firing).await {
+            warn!("Failed to re-send firing alerts to {}: {}", notifier.name(), e);
+        }
+    });
+    futures::future::join_all(refreshes).await;
+}
+
+/// Periodically evaluates the same data as `/api/temperatures` and sends alert notifications.
+pub async fn run(state: AppState) -> anyhow::Result<()> {
+    let config = state.config.clone();
+    let mut engine = AlertEngine::new(config.alerting_pending()?, config.alerting.hysteresis);
+    let notifiers = notifiers_from_config(&config, &state.client)?;
+    let params = QueryParams {
+        detail: Some(Detail::Sensors),
+        ..QueryParams::default()
+    };
+
+    info!(
+        "Alerting enabled: evaluating every {}, {} notifier(s)",
+        config.alerting.interval,
+        notifiers.len()
+    );
+
+    let mut ticker = tokio::time::interval(config.alerting_interval()?);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+
+        let response = match tokio::time::timeout(state.request_deadline, fetch_temperatures(&state, &params)).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(status)) => {
+                warn!("Alert evaluation skipped, fetching temperatures failed with {}", status);
+                continue;
+            }
+            Err(_) => {
+                warn!("Alert evaluation skipped, fetching temperatures exceeded {:?}", state.request_deadline);
+                continue;
+            }
+        };
+
+        let events = engine.evaluate(&response.measurements, Utc::now());
+        for event in &events {
+            info!(
+                "Alert {:?} for {}: {:?} at {}°C",
+                event.status, event.key(), event.severity, event.temperature
+            );
+        }
+        dispatch(&notifiers, &events).await;
+        refresh(&notifiers, &engine.firing()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensors::SensorMeasurement;
+    use axum::{extract::State, routing::post, Json, Router};
+    use tokio::sync::Mutex;
+
+    pub(super) fn measurement(node: &str, temperature: f64) -> TemperatureMeasurement {
+        let thresholds = Limits::new(Some(70.0), Some(85.0));
+        let status = thresholds.status(temperature);
+        TemperatureMeasurement {
+            node: node.to_string(),
+            cluster: None,
+            minutely_temperature: Some(temperature),
+            hourly_temperature: Some(temperature),
+            daily_temperature: Some(temperature),
+            missing: Default::default(),
+            last_seen: None,
+            status,
+            windows: None,
+            sensors: Some(vec![SensorMeasurement {
+                instance: "10.0.0.1:9100".to_string(),
+                chip: "platform_coretemp_0".to_string(),
+                chip_name: None,
+                sensor: "temp1".to_string(),
+                label: Some("Package id 0".to_string()),
+                minutely_temperature: Some(temperature),
+                hourly_temperature: Some(temperature),
+                daily_temperature: Some(temperature),
+                missing: Default::default(),
+                windows: None,
+                status,
+                thresholds,
+                headroom: None,
+            }]),
+        }
+    }
+
+    pub(super) fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap() + chrono::Duration::minutes(minutes)
+    }
+
+    #[test]
+    fn alert_fires_after_pending_duration() {
+        let mut engine = AlertEngine::new(Duration::from_secs(120), 2.0);
+
+        assert!(engine.evaluate(&[measurement("blade001", 75.0)], at(0)).is_empty());
+        assert!(engine.evaluate(&[measurement("blade001", 76.0)], at(1)).is_empty());
+        let events = engine.evaluate(&[measurement("blade001", 76.0)], at(2));
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].status, AlertStatus::Firing);
+        assert_eq!(events[0].severity, Status::Warning);
+        assert_eq!(events[0].threshold, Some(70.0));
+        assert_eq!(events[0].starts_at, at(0));
+        assert_eq!(events[0].sensor.as_ref().unwrap().label.as_deref(), Some("Package id 0"));
+    }
+
+    #[test]
+    fn pending_alert_is_dropped_silently() {
+        let mut engine = AlertEngine::new(Duration::from_secs(120), 2.0);
+
+        engine.evaluate(&[measurement("blade001", 75.0)], at(0));
+        assert!(engine.evaluate(&[measurement("blade001", 60.0)], at(1)).is_empty());
+        assert!(engine.evaluate(&[measurement("blade001", 75.0)], at(2)).is_empty());
+    }
+
+    #[test]
+    fn resolve_waits_for_hysteresis() {
+        let mut engine = AlertEngine::new(Duration::ZERO, 2.0);
+
+        assert_eq!(engine.evaluate(&[measurement("blade001", 71.0)], at(0)).len(), 1);
+        // Below the threshold but within the hysteresis band
+        assert!(engine.evaluate(&[measurement("blade001", 69.0)], at(1)).is_empty());
+        let events = engine.evaluate(&[measurement("blade001", 67.5)], at(2));
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].status, AlertStatus::Resolved);
+        assert_eq!(events[0].ends_at, Some(at(2)));
+    }
+
+    #[test]
+    fn severity_changes_are_notified() {
+        let mut engine = AlertEngine::new(Duration::ZERO, 2.0);
+
+        engine.evaluate(&[measurement("blade001", 71.0)], at(0));
+        let escalated = engine.evaluate(&[measurement("blade001", 86.0)], at(1));
+        assert_eq!(escalated[0].severity, Status::Critical);
+        assert_eq!(escalated[0].previous_severity, Some(Status::Warning));
+
+        assert!(engine.evaluate(&[measurement("blade001", 84.0)], at(2)).is_empty());
+        let downgraded = engine.evaluate(&[measurement("blade001", 80.0)], at(3));
+        assert_eq!(downgraded[0].severity, Status::Warning);
+        assert_eq!(downgraded[0].status, AlertStatus::Firing);
+    }
+
+    #[test]
+    fn sensors_without_limits_do_not_block_resolving() {
+        // An NVMe composite next to the CPU, without limits of its own
+        let with_drive = |temperature: f64| {
+            let mut measurement = measurement("blade001", temperature);
+            let sensors = measurement.sensors.as_mut().unwrap();
+            let mut drive = sensors[0].clone();
+            drive.chip = "nvme_nvme0".to_string();
+            drive.label = Some("Composite".to_string());
+            drive.thresholds = Limits::default();
+            drive.status = Status::Unknown;
+            sensors.push(drive);
+            measurement.status = worst_judged(sensors.iter().map(|sensor| sensor.status));
+            measurement
+        };
+        let mut engine = AlertEngine::new(Duration::ZERO, 2.0);
+
+        let fired = engine.evaluate(&[with_drive(90.0)], at(0));
+        assert_eq!(fired[0].sensor.as_ref().unwrap().chip, "platform_coretemp_0");
+        let events = engine.evaluate(&[with_drive(50.0)], at(1));
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].status, AlertStatus::Resolved);
+        assert!(engine.firing().is_empty());
+    }
+
+    #[test]
+    fn missing_nodes_keep_their_state() {
+        let mut engine = AlertEngine::new(Duration::ZERO, 2.0);
+
+        engine.evaluate(&[measurement("blade001", 90.0)], at(0));
+        assert!(engine.evaluate(&[], at(1)).is_empty());
+        assert!(engine.evaluate(&[measurement("blade001", 90.0)], at(2)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn webhook_posts_events_as_json() {
+        let received: Arc<Mutex<Vec<serde_json::Value>>> = Arc::default();
+        let receiver = Router::new()
+            .route(
+                "/hook",
+                post(|State(received): State<Arc<Mutex<Vec<serde_json::Value>>>>, Json(body): Json<serde_json::Value>| async move {
+                    received.lock().await.push(body);
+                }),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+        let webhook = WebhookConfig {
+            name: "test".to_string(),
+            url: format!("http://{}/hook", addr),
+            headers: BTreeMap::new(),
+        };
+        let notifier: Arc<dyn Notifier> = Arc::new(WebhookNotifier::new(&webhook, Client::new(), Duration::from_secs(5)));
+        let mut engine = AlertEngine::new(Duration::ZERO, 2.0);
+        let events = engine.evaluate(&[measurement("blade001", 90.0)], at(0));
+
+        dispatch(&[notifier], &events).await;
+
+        let received = received.lock().await;
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0]["status"], "firing");
+        assert_eq!(received[0]["node"], "blade001");
+        assert_eq!(received[0]["severity"], "critical");
+        assert_eq!(received[0]["threshold"], 85.0);
+    }
+}
@@ -0,0 +1,339 @@
+use super::{is_timeout, InstantVector, MetricsBackend, QueryError, RangeSeries, TimedOut};
+use crate::config::{parse_duration, Config};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+// Expired entries are swept once the cache grows beyond this, e.g. from many distinct ?windows=
+const SWEEP_THRESHOLD: usize = 1024;
+
+/// Chooses how long a query result stays fresh, based on the longest range selector in the query.
+#[derive(Debug, Clone)]
+pub struct CachePolicy {
+    default_ttl: Duration,
+    // (window, ttl) ordered by window
+    ttls: Vec<(Duration, Duration)>,
+}
+
+impl CachePolicy {
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        Ok(Self {
+            default_ttl: config.cache_default_ttl()?,
+            ttls: config.cache_ttls()?,
+        })
+    }
+
+    fn ttl_for(&self, query: &str) -> Duration {
+        let Some(window) = query_window(query) else {
+            return self.default_ttl;
+        };
+        self.ttls
+            .iter()
+            .rev()
+            .find(|(configured, _)| *configured <= window)
+            .map_or(self.default_ttl, |(_, ttl)| *ttl)
+    }
+}
+
+// Longest `[range]` in a query, subquery resolutions (`[1h:1m]`) are ignored
+fn query_window(query: &str) -> Option<Duration> {
+    query
+        .split('[')
+        .skip(1)
+        .filter_map(|part| part.split_once(']'))
+        .filter_map(|(range, _)| parse_duration(range.split(':').next()?).ok())
+        .max()
+}
+
+enum Slot<V> {
+    Ready { value: Arc<V>, expires: Instant },
+    // A fetch is in flight, followers wait for its outcome
+    Pending(watch::Receiver<Outcome<V>>),
+}
+
+// Set once the leader finished
+type Outcome<V> = Option<Result<Arc<V>, SharedError>>;
+
+// anyhow::Error is not Clone, so followers get the message plus what the error is classified by: the query error,
+// or whether it timed out
+#[derive(Clone)]
+enum SharedError {
+    Query(QueryError),
+    Timeout(String),
+    Other(String),
+}
+
+impl SharedError {
+    fn new(error: &anyhow::Error) -> Self {
+        match error.downcast_ref::<QueryError>() {
+            Some(query) => Self::Query(query.clone()),
+            None if is_timeout(error) => Self::Timeout(format!("{:#}", error)),
+            None => Self::Other(format!("{:#}", error)),
+        }
+    }
+
+    fn into_error(self) -> anyhow::Error {
+        match self {
+            Self::Query(query) => query.into(),
+            Self::Timeout(message) => TimedOut(message).into(),
+            Self::Other(message) => anyhow!(message),
+        }
+    }
+}
+
+enum Role<V> {
+    Lead(watch::Sender<Outcome<V>>),
+    Follow(watch::Receiver<Outcome<V>>),
+}
+
+/// Cache where concurrent misses for the same key share a single fetch. Failures are not cached.
+struct SingleFlight<V> {
+    slots: Mutex<HashMap<String, Slot<V>>>,
+}
+
+impl<V: Send + Sync> SingleFlight<V> {
+    fn new() -> Self {
+        Self {
+            slots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn get(&self, key: &str, ttl: Duration, fetch: impl Future<Output = anyhow::Result<V>>) -> anyhow::Result<Arc<V>> {
+        let mut fetch = Some(fetch);
+        loop {
+            let role = {
+                let mut slots = self.slots.lock().unwrap();
+                match slots.get(key) {
+                    Some(Slot::Ready { value, expires }) if *expires > Instant::now() => return Ok(value.clone()),
+                    Some(Slot::Pending(receiver)) => Role::Follow(receiver.clone()),
+                    _ => {
+                        let (sender, receiver) = watch::channel(None);
+                        slots.insert(key.to_string(), Slot::Pending(receiver));
+                        Role::Lead(sender)
+                    }
+                }
+            };
+            let mut receiver = match role {
+                Role::Follow(receiver) => receiver,
+                Role::Lead(sender) => {
+                    // Only the first miss leads, and a follower only leads after the leader went away
+                    let fetch = fetch.take().expect("a request leads at most once");
+                    return self.lead(key, ttl, sender, fetch).await;
+                }
+            };
+            let outcome = match receiver.wait_for(Option::is_some).await {
+                Ok(outcome) => outcome.clone(),
+                // The leading request was cancelled, e.g. by its deadline; try again
+                Err(_) => continue,
+            };
+            return outcome.expect("waited for an outcome").map_err(SharedError::into_error);
+        }
+    }
+
+    async fn lead(
+        &self,
+        key: &str,
+        ttl: Duration,
+        sender: watch::Sender<Outcome<V>>,
+        fetch: impl Future<Output = anyhow::Result<V>>,
+    ) -> anyhow::Result<Arc<V>> {
+        let guard = PendingGuard { slots: &self.slots, key };
+        let result = fetch.await.map(Arc::new);
+        if let Ok(value) = &result {
+            let mut slots = self.slots.lock().unwrap();
+            slots.insert(
+                key.to_string(),
+                Slot::Ready {
+                    value: value.clone(),
+                    expires: Instant::now() + ttl,
+                },
+            );
+            if slots.len() > SWEEP_THRESHOLD {
+                let now = Instant::now();
+                slots.retain(|_, slot| !matches!(slot, Slot::Ready { expires, .. } if *expires <= now));
+            }
+        }
+        drop(guard);
+        sender.send_replace(Some(result.as_ref().map(Arc::clone).map_err(SharedError::new)));
+        result
+    }
+}
+
+// Removes the pending slot when the leader fails or is dropped mid-fetch, so the next request retries
+struct PendingGuard<'a, V> {
+    slots: &'a Mutex<HashMap<String, Slot<V>>>,
+    key: &'a str,
+}
+
+impl<V> Drop for PendingGuard<'_, V> {
+    fn drop(&mut self) {
+        let mut slots = self.slots.lock().unwrap();
+        if matches!(slots.get(self.key), Some(Slot::Pending(_))) {
+            slots.remove(self.key);
+        }
+    }
+}
+
+/// Backend decorator caching instant queries and series lookups; range queries pass through.
+pub struct CachingBackend {
+    inner: Arc<dyn MetricsBackend>,
+    policy: CachePolicy,
+    instant: SingleFlight<InstantVector>,
+    series: SingleFlight<Vec<HashMap<String, String>>>,
+}
+
+impl CachingBackend {
+    pub fn new(inner: Arc<dyn MetricsBackend>, policy: CachePolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            instant: SingleFlight::new(),
+            series: SingleFlight::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl MetricsBackend for CachingBackend {
+    async fn instant_query(&self, query: &str) -> anyhow::Result<InstantVector> {
+        let ttl = self.policy.ttl_for(query);
+        let vector = self.instant.get(query, ttl, self.inner.instant_query(query)).await?;
+        Ok(vector.as_ref().clone())
+    }
+
+    async fn range_query(
+        &self,
+        query: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        step: Duration,
+    ) -> anyhow::Result<Vec<RangeSeries>> {
+        self.inner.range_query(query, start, end, step).await
+    }
+
+    async fn series(
+        &self,
+        selector: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<HashMap<String, String>>> {
+        // Callers look at a sliding recent window, so the selector alone identifies the lookup
+        let series = self
+            .series
+            .get(selector, self.policy.default_ttl, self.inner.series(selector, start, end))
+            .await?;
+        Ok(series.as_ref().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory::sample;
+    use crate::error::ApiError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Answers every instant query after a short delay, counting upstream calls
+    #[derive(Default)]
+    struct SlowBackend {
+        calls: AtomicUsize,
+        fail: bool,
+        time_out: bool,
+    }
+
+    #[async_trait]
+    impl MetricsBackend for SlowBackend {
+        async fn instant_query(&self, _query: &str) -> anyhow::Result<InstantVector> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            if self.fail {
+                anyhow::bail!("upstream unavailable");
+            }
+            if self.time_out {
+                tokio::time::timeout(Duration::ZERO, std::future::pending::<()>()).await?;
+            }
+            Ok(vec![sample(&[("instance", "10.0.0.1:9100")], 60.0)].into())
+        }
+
+        async fn range_query(
+            &self,
+            _query: &str,
+            _start: DateTime<Utc>,
+            _end: DateTime<Utc>,
+            _step: Duration,
+        ) -> anyhow::Result<Vec<RangeSeries>> {
+            Ok(Vec::new())
+        }
+
+        async fn series(
+            &self,
+            _selector: &str,
+            _start: DateTime<Utc>,
+            _end: DateTime<Utc>,
+        ) -> anyhow::Result<Vec<HashMap<String, String>>> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn policy() -> CachePolicy {
+        CachePolicy::from_config(&Config::default()).unwrap()
+    }
+
+    #[test]
+    fn ttl_follows_the_query_window() {
+        let policy = policy();
+
+        assert_eq!(policy.ttl_for("max_over_time(node_hwmon_temp_celsius[1m])"), Duration::from_secs(15));
+        assert_eq!(policy.ttl_for("max_over_time(node_hwmon_temp_celsius[6h])"), Duration::from_secs(60));
+        assert_eq!(policy.ttl_for("max_over_time(node_hwmon_temp_celsius[7d])"), Duration::from_secs(300));
+        assert_eq!(policy.ttl_for("max_over_time(rate(x[5m])[1d:1m])"), Duration::from_secs(300));
+        // Shorter than every configured window, or no range at all
+        assert_eq!(policy.ttl_for("max_over_time(node_hwmon_temp_celsius[30s])"), Duration::from_secs(15));
+        assert_eq!(policy.ttl_for("node_hwmon_temp_crit_celsius"), Duration::from_secs(15));
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_share_one_fetch() {
+        let upstream = Arc::new(SlowBackend::default());
+        let cache = CachingBackend::new(upstream.clone(), policy());
+
+        let results = futures::future::join_all((0..50).map(|_| cache.instant_query("max_over_time(x[1d])"))).await;
+        assert!(results.iter().all(|result| result.as_ref().unwrap().samples.len() == 1));
+        cache.instant_query("max_over_time(x[1d])").await.unwrap();
+
+        assert_eq!(upstream.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn failures_are_shared_but_not_cached() {
+        let upstream = Arc::new(SlowBackend { fail: true, ..SlowBackend::default() });
+        let cache = CachingBackend::new(upstream.clone(), policy());
+
+        let results = futures::future::join_all((0..10).map(|_| cache.instant_query("x"))).await;
+        assert!(results.iter().all(|result| result.is_err()));
+        assert_eq!(upstream.calls.load(Ordering::SeqCst), 1);
+
+        assert!(cache.instant_query("x").await.is_err());
+        assert_eq!(upstream.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn followers_get_the_classification_of_the_failure() {
+        let upstream = Arc::new(SlowBackend { time_out: true, ..SlowBackend::default() });
+        let cache = CachingBackend::new(upstream.clone(), policy());
+
+        let results = futures::future::join_all((0..10).map(|_| cache.instant_query("x"))).await;
+
+        assert_eq!(upstream.calls.load(Ordering::SeqCst), 1);
+        for result in results {
+            let error = ApiError::upstream(&result.unwrap_err());
+            assert!(matches!(error, ApiError::UpstreamTimeout(_)), "{:?}", error);
+        }
+    }
+}
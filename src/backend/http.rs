@@ -0,0 +1,358 @@
+use super::{InstantSample, InstantVector, MetricsBackend, QueryError, RangeSeries};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::{Client, StatusCode};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::warn;
+
+/// Backend for the Prometheus HTTP query API, which VictoriaMetrics implements as well.
+pub struct HttpBackend {
+    client: Client,
+    base_url: String,
+    retry: RetryPolicy,
+}
+
+/// Retry settings for upstream GETs; all query API calls are idempotent, so they are safe to repeat.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff)
+    }
+}
+
+// Failed attempt, flagged with whether repeating the request could help
+struct AttemptError {
+    error: anyhow::Error,
+    retryable: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PrometheusResponse<T> {
+    status: String,
+    // Absent on errors
+    data: Option<T>,
+    #[serde(default)]
+    error_type: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    warnings: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "resultType", content = "result", rename_all = "lowercase")]
+enum PrometheusData {
+    Vector(Vec<VectorResult>),
+    Matrix(Vec<MatrixResult>),
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct VectorResult {
+    metric: HashMap<String, String>,
+    value: (f64, String),
+}
+
+#[derive(Debug, Deserialize)]
+struct MatrixResult {
+    metric: HashMap<String, String>,
+    values: Vec<(f64, String)>,
+}
+
+impl HttpBackend {
+    pub fn new(client: Client, base_url: impl Into<String>, retry: RetryPolicy) -> Self {
+        Self {
+            client,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            retry,
+        }
+    }
+
+    /// Performs a query API call, returning its data and any warnings.
+    async fn get<T: DeserializeOwned>(&self, path: &str, params: &[(&str, String)]) -> anyhow::Result<(T, Vec<String>)> {
+        let url = format!("{}{}", self.base_url, path);
+        let mut attempt = 0;
+        loop {
+            match self.try_get(&url, params).await {
+                Ok(data) => return Ok(data),
+                Err(e) if e.retryable && attempt < self.retry.max_retries => {
+                    let delay = self.retry.backoff(attempt);
+                    attempt += 1;
+                    warn!(
+                        "Request to {} failed (attempt {}/{}): {}, retrying in {:?}",
+                        url,
+                        attempt,
+                        self.retry.max_retries + 1,
+                        e.error,
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(e.error),
+            }
+        }
+    }
+
+    async fn try_get<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        params: &[(&str, String)],
+    ) -> Result<(T, Vec<String>), AttemptError> {
+        let response = self.client.get(url).query(params).send().await.map_err(|e| AttemptError {
+            retryable: e.is_connect() || e.is_timeout() || e.is_request(),
+            error: e.into(),
+        })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(Self::failed_response(status, response).await);
+        }
+
+        let response = response.json::<PrometheusResponse<T>>().await.map_err(|e| AttemptError {
+            retryable: e.is_timeout(),
+            error: e.into(),
+        })?;
+
+        match response.data {
+            Some(data) if response.status == "success" => Ok((data, response.warnings)),
+            _ => Err(AttemptError {
+                error: response.query_error().into(),
+                retryable: false,
+            }),
+        }
+    }
+
+    // Prometheus answers failed queries with an error status and a body naming the errorType, e.g. 503 for
+    // `timeout` and 422 for `execution`. Those are surfaced as they are and not retried, since repeating an
+    // expensive query rarely helps; only overload and gateway errors without such a body are.
+    async fn failed_response(status: StatusCode, response: reqwest::Response) -> AttemptError {
+        let body = match response.bytes().await {
+            Ok(body) => body,
+            Err(e) => {
+                return AttemptError {
+                    retryable: e.is_timeout() || status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+                    error: e.into(),
+                }
+            }
+        };
+        match serde_json::from_slice::<PrometheusResponse<IgnoredAny>>(&body) {
+            Ok(response) if response.status == "error" => AttemptError {
+                error: response.query_error().into(),
+                retryable: false,
+            },
+            _ => AttemptError {
+                error: anyhow::anyhow!("upstream responded with {}", status),
+                retryable: status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+            },
+        }
+    }
+}
+
+impl<T> PrometheusResponse<T> {
+    fn query_error(self) -> QueryError {
+        QueryError {
+            error_type: self.error_type.unwrap_or_else(|| "unknown".to_string()),
+            message: self.error.unwrap_or_else(|| format!("status {:?}", self.status)),
+        }
+    }
+}
+
+#[async_trait]
+impl MetricsBackend for HttpBackend {
+    async fn instant_query(&self, query: &str) -> anyhow::Result<InstantVector> {
+        let (data, warnings) = self.get("/api/v1/query", &[("query", query.to_string())]).await?;
+
+        match data {
+            PrometheusData::Vector(results) => Ok(InstantVector {
+                samples: results
+                    .into_iter()
+                    .filter_map(|result| {
+                        Some(InstantSample {
+                            value: result.value.1.parse().ok()?,
+                            timestamp: result.value.0,
+                            metric: result.metric,
+                        })
+                    })
+                    .collect(),
+                warnings,
+            }),
+            _ => Err(anyhow::anyhow!("Prometheus query returned a non-vector result")),
+        }
+    }
+
+    async fn range_query(
+        &self,
+        query: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        step: Duration,
+    ) -> anyhow::Result<Vec<RangeSeries>> {
+        let params = [
+            ("query", query.to_string()),
+            ("start", start.timestamp().to_string()),
+            ("end", end.timestamp().to_string()),
+            ("step", format!("{}s", step.as_secs().max(1))),
+        ];
+        let (data, warnings) = self.get("/api/v1/query_range", &params).await?;
+        for warning in warnings {
+            warn!("Range query {} returned a warning: {}", query, warning);
+        }
+
+        match data {
+            PrometheusData::Matrix(results) => Ok(results
+                .into_iter()
+                .map(|result| RangeSeries {
+                    values: result
+                        .values
+                        .into_iter()
+                        .filter_map(|(timestamp, value)| Some((timestamp, value.parse().ok()?)))
+                        .collect(),
+                    metric: result.metric,
+                })
+                .collect()),
+            _ => Err(anyhow::anyhow!("Prometheus range query returned a non-matrix result")),
+        }
+    }
+
+    async fn series(
+        &self,
+        selector: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<HashMap<String, String>>> {
+        let params = [
+            ("match[]", selector.to_string()),
+            ("start", start.timestamp().to_string()),
+            ("end", end.timestamp().to_string()),
+        ];
+        let (series, _) = self.get("/api/v1/series", &params).await?;
+        Ok(series)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::StatusCode as HttpStatus, routing::get, Json, Router};
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    type Replies = Arc<Mutex<Vec<(HttpStatus, Value)>>>;
+
+    // Serves the given replies in order, repeating the last one, and counts the requests
+    async fn stub(replies: Vec<(HttpStatus, Value)>) -> (HttpBackend, Arc<AtomicUsize>) {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let replies: Replies = Arc::new(Mutex::new(replies));
+        let counter = attempts.clone();
+        let app = Router::new()
+            .route(
+                "/api/v1/query",
+                get(|State(replies): State<Replies>| async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let mut replies = replies.lock().unwrap();
+                    let (status, body) = if replies.len() > 1 { replies.remove(0) } else { replies[0].clone() };
+                    (status, Json(body))
+                }),
+            )
+            .with_state(replies);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let retry = RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+        };
+        (HttpBackend::new(Client::new(), format!("http://{}", addr), retry), attempts)
+    }
+
+    fn prometheus_error(error_type: &str, error: &str) -> Value {
+        json!({ "status": "error", "errorType": error_type, "error": error })
+    }
+
+    #[tokio::test]
+    async fn error_bodies_are_surfaced_without_retrying() {
+        let (backend, attempts) =
+            stub(vec![(HttpStatus::SERVICE_UNAVAILABLE, prometheus_error("timeout", "query timed out in query execution"))])
+                .await;
+
+        let error = backend.instant_query("up").await.unwrap_err();
+
+        let query_error = error.downcast_ref::<QueryError>().expect("errorType is kept");
+        assert_eq!(query_error.error_type, "timeout");
+        assert_eq!(query_error.message, "query timed out in query execution");
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    fn success() -> Value {
+        json!({
+            "status": "success",
+            "data": { "resultType": "vector", "result": [{ "metric": { "instance": "10.0.0.1:9100" }, "value": [1700000000, "73.3"] }] }
+        })
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        let (backend, attempts) = stub(vec![
+            (HttpStatus::BAD_GATEWAY, json!("bad gateway")),
+            (HttpStatus::TOO_MANY_REQUESTS, json!("slow down")),
+            (HttpStatus::OK, success()),
+        ])
+        .await;
+
+        let vector = backend.instant_query("up").await.unwrap();
+
+        assert_eq!(vector.samples.len(), 1);
+        assert_eq!(vector.samples[0].value, 73.3);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let (backend, attempts) =
+            stub(vec![(HttpStatus::BAD_REQUEST, prometheus_error("bad_data", "parse error at char 3"))]).await;
+
+        let error = backend.instant_query("up{").await.unwrap_err();
+
+        assert_eq!(error.downcast_ref::<QueryError>().unwrap().error_type, "bad_data");
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let (backend, attempts) = stub(vec![(HttpStatus::SERVICE_UNAVAILABLE, json!("overloaded"))]).await;
+
+        let error = backend.instant_query("up").await.unwrap_err();
+
+        assert_eq!(error.to_string(), "upstream responded with 503 Service Unavailable");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let retry = RetryPolicy {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(1),
+        };
+
+        let delays: Vec<Duration> = (0..4).map(|attempt| retry.backoff(attempt)).collect();
+
+        assert_eq!(delays, [200, 400, 800, 1000].map(Duration::from_millis));
+    }
+}
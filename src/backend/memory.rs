@@ -0,0 +1,109 @@
+use super::{InstantSample, InstantVector, MetricsBackend, QueryError, RangeSeries};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Fixture backend answering from canned results keyed by the exact query string.
+/// Unknown queries return an empty result, like a PromQL query without matches.
+#[derive(Default)]
+pub struct InMemoryBackend {
+    instant: HashMap<String, InstantVector>,
+    // Queries failing with a Prometheus error
+    errors: HashMap<String, QueryError>,
+    range: HashMap<String, Vec<RangeSeries>>,
+    series: HashMap<String, Vec<HashMap<String, String>>>,
+    // Simulates an unreachable upstream
+    unavailable: bool,
+}
+
+impl InMemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_instant(mut self, query: impl Into<String>, samples: Vec<InstantSample>) -> Self {
+        self.instant.entry(query.into()).or_default().samples = samples;
+        self
+    }
+
+    pub fn with_warning(mut self, query: impl Into<String>, warning: &str) -> Self {
+        self.instant.entry(query.into()).or_default().warnings.push(warning.to_string());
+        self
+    }
+
+    pub fn with_error(mut self, query: impl Into<String>, error_type: &str, message: &str) -> Self {
+        let error = QueryError {
+            error_type: error_type.to_string(),
+            message: message.to_string(),
+        };
+        self.errors.insert(query.into(), error);
+        self
+    }
+
+    pub fn with_range(mut self, query: impl Into<String>, series: Vec<RangeSeries>) -> Self {
+        self.range.insert(query.into(), series);
+        self
+    }
+
+    /// Fails every query, like an upstream that cannot be reached.
+    pub fn unavailable() -> Self {
+        Self {
+            unavailable: true,
+            ..Self::default()
+        }
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        if self.unavailable {
+            anyhow::bail!("upstream unavailable");
+        }
+        Ok(())
+    }
+
+    pub fn with_series(mut self, selector: impl Into<String>, series: Vec<HashMap<String, String>>) -> Self {
+        self.series.insert(selector.into(), series);
+        self
+    }
+}
+
+/// Builds an instant sample from label pairs, e.g. `sample(&[("instance", "10.0.0.1:9100")], 42.0)`.
+pub fn sample(labels: &[(&str, &str)], value: f64) -> InstantSample {
+    InstantSample {
+        metric: labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        timestamp: Utc::now().timestamp() as f64,
+        value,
+    }
+}
+
+#[async_trait]
+impl MetricsBackend for InMemoryBackend {
+    async fn instant_query(&self, query: &str) -> anyhow::Result<InstantVector> {
+        self.check()?;
+        if let Some(error) = self.errors.get(query) {
+            return Err(error.clone().into());
+        }
+        Ok(self.instant.get(query).cloned().unwrap_or_default())
+    }
+
+    async fn range_query(
+        &self,
+        query: &str,
+        _start: DateTime<Utc>,
+        _end: DateTime<Utc>,
+        _step: Duration,
+    ) -> anyhow::Result<Vec<RangeSeries>> {
+        self.check()?;
+        Ok(self.range.get(query).cloned().unwrap_or_default())
+    }
+
+    async fn series(
+        &self,
+        selector: &str,
+        _start: DateTime<Utc>,
+        _end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<HashMap<String, String>>> {
+        self.check()?;
+        Ok(self.series.get(selector).cloned().unwrap_or_default())
+    }
+}
@@ -0,0 +1,102 @@
+mod cache;
+mod http;
+#[cfg(test)]
+pub mod memory;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+pub use cache::{CachePolicy, CachingBackend};
+pub use http::{HttpBackend, RetryPolicy};
+
+/// One element of an instant vector result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstantSample {
+    pub metric: HashMap<String, String>,
+    pub timestamp: f64,
+    pub value: f64,
+}
+
+/// Instant vector result together with the warnings the upstream attached to it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InstantVector {
+    pub samples: Vec<InstantSample>,
+    // e.g. VictoriaMetrics hinting that a query touched too many series
+    pub warnings: Vec<String>,
+}
+
+impl From<Vec<InstantSample>> for InstantVector {
+    fn from(samples: Vec<InstantSample>) -> Self {
+        Self {
+            samples,
+            warnings: Vec::new(),
+        }
+    }
+}
+
+/// Error reported by the query API in its response body, as opposed to transport failures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+    // Prometheus `errorType`, e.g. bad_data, timeout or execution
+    pub error_type: String,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Prometheus query failed ({}): {}", self.error_type, self.message)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Upstream call that ran out of time, standing in for the original error where that cannot be shared.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimedOut(pub String);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Whether an upstream call failed by running out of time rather than by being refused.
+pub fn is_timeout(error: &anyhow::Error) -> bool {
+    error.is::<TimedOut>()
+        || error.is::<tokio::time::error::Elapsed>()
+        || error.downcast_ref::<reqwest::Error>().is_some_and(reqwest::Error::is_timeout)
+}
+
+/// One series of a range (matrix) result, values are (unix timestamp, value) pairs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeSeries {
+    pub metric: HashMap<String, String>,
+    pub values: Vec<(f64, f64)>,
+}
+
+/// Source of metric data speaking PromQL, e.g. VictoriaMetrics or Prometheus.
+#[async_trait]
+pub trait MetricsBackend: Send + Sync {
+    async fn instant_query(&self, query: &str) -> anyhow::Result<InstantVector>;
+
+    async fn range_query(
+        &self,
+        query: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        step: Duration,
+    ) -> anyhow::Result<Vec<RangeSeries>>;
+
+    /// Returns the label sets of all series matching `selector` between `start` and `end`.
+    async fn series(
+        &self,
+        selector: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<HashMap<String, String>>>;
+}
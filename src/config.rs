@@ -0,0 +1,885 @@
+use crate::promql::{
+    is_valid_label_name, is_valid_metric_name, Aggregation, Expr, Function, LabelMatcher, MatchOp, RangeFunction,
+    Selector,
+};
+use crate::windows::Stat;
+use anyhow::{anyhow, bail, Context};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use tracing::info;
+
+// Location of the TOML configuration file, overridable with TEMPERATURE_MONITOR_CONFIG
+const DEFAULT_CONFIG_PATH: &str = "/etc/temperature-monitor/config.toml";
+const ENV_PREFIX: &str = "TEMPERATURE_MONITOR_";
+// Resolution of the last seen subquery
+const LAST_SEEN_STEP: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub upstream: UpstreamConfig,
+    pub query: QueryConfig,
+    pub thresholds: ThresholdsConfig,
+    pub alerting: AlertingConfig,
+    pub stream: StreamConfig,
+    pub cache: CacheConfig,
+    pub nodes: NodesConfig,
+    pub datasources: DatasourcesConfig,
+    pub federation: FederationConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen_addr: String,
+    // Upper bound for answering one API request, including all upstream retries
+    pub request_deadline: String,
+    // How long the last good response may be served while the upstream is down, 503 afterwards
+    pub max_staleness: String,
+    // Budget per window query; windows still running afterwards are reported as failed so the others
+    // can be returned, so this must be below request_deadline
+    pub window_deadline: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamConfig {
+    // Upstream of the default datasource, unless `datasources.sources` lists it
+    pub url: String,
+    pub connect_timeout: String,
+    pub request_timeout: String,
+    pub max_retries: u32,
+    pub retry_backoff: String,
+    pub max_retry_backoff: String,
+    // How long idle pooled connections to the upstream are kept open
+    pub pool_idle_timeout: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueryConfig {
+    pub metric: String,
+    // Extra label matchers applied to every temperature query, e.g. { job = "node-exporter" }
+    pub selectors: BTreeMap<String, String>,
+    pub windows: WindowsConfig,
+    // Longest window clients may request with ?windows=
+    pub max_window: String,
+    // Request filters from ?node= and ?labels=, added to the configured selectors; never read from the file
+    #[serde(skip)]
+    pub matchers: Vec<LabelMatcher>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowsConfig {
+    pub minutely: String,
+    pub hourly: String,
+    pub daily: String,
+}
+
+/// Temperature limits in °C. The most specific matching rule wins: sensor rules, then node groups, then the
+/// global values, then the chip's own hwmon limits; warning and critical fall back independently.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThresholdsConfig {
+    // Fall back to node_hwmon_temp_max_celsius / node_hwmon_temp_crit_celsius as warning / critical
+    pub hwmon_limits: bool,
+    pub warning: Option<f64>,
+    pub critical: Option<f64>,
+    pub groups: Vec<GroupThresholds>,
+    pub sensors: Vec<SensorThresholds>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupThresholds {
+    pub name: String,
+    // Node name patterns, `*` and `?` wildcards are supported
+    pub nodes: Vec<String>,
+    pub warning: Option<f64>,
+    pub critical: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SensorThresholds {
+    // All given patterns must match; omitted ones match any sensor
+    pub nodes: Option<Vec<String>>,
+    pub chip: Option<String>,
+    pub sensor: Option<String>,
+    pub label: Option<String>,
+    pub warning: Option<f64>,
+    pub critical: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertingConfig {
+    pub enabled: bool,
+    // How often the temperatures are evaluated
+    pub interval: String,
+    // How long a node must stay above a threshold before the alert fires
+    pub pending: String,
+    // Degrees a firing node must drop below the threshold before the alert resolves or downgrades
+    pub hysteresis: f64,
+    pub webhook_timeout: String,
+    pub webhooks: Vec<WebhookConfig>,
+    pub alertmanagers: Vec<AlertmanagerConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    // TTL of queries without a range (e.g. hwmon limits, the pod mapping) or shorter than every window in `ttls`
+    pub default_ttl: String,
+    // TTL by query window, a query uses the entry of the longest window not exceeding its own
+    pub ttls: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodesConfig {
+    // A node without a minutely reading whose last sample is older than this is reported offline
+    pub offline_after: String,
+    // How far back the last sample of a node is searched; nodes silent for longer have no last_seen
+    pub last_seen_lookback: String,
+    // Port node-exporter listens on, exporter pod IPs are mapped to `<pod_ip>:<exporter_port>` instances
+    pub exporter_port: u16,
+    // Regex matching the node-exporter pod names in kube_pod_info
+    pub exporter_pods: String,
+    // TOML file of `"instance or host" = "node"` entries, for instances no other source can map
+    pub mapping_file: Option<String>,
+    // How often the node mapping is rebuilt in the background
+    pub refresh_interval: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatasourcesConfig {
+    // Datasource used when a request does not pass ?datasource=
+    pub default: String,
+    // Let clients pick another listed datasource with ?datasource=; otherwise only the default is accepted
+    pub allow_selection: bool,
+    // Name to upstream URL, e.g. { staging = "http://..." }; these names are the only ones clients may select
+    pub sources: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FederationConfig {
+    // Datasources queried together for one fleet response, each reported as a cluster; empty disables federation
+    pub clusters: Vec<String>,
+    // Budget per cluster, so one slow cluster is reported as failed instead of costing the others their results;
+    // must be above server.window_deadline and below server.request_deadline
+    pub cluster_deadline: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StreamConfig {
+    // How often the live poller refreshes temperatures while clients are subscribed
+    pub interval: String,
+    // Interval of SSE keep-alive comments, so proxies don't close idle streams
+    pub keep_alive: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub name: String,
+    pub url: String,
+    // Extra request headers, e.g. an Authorization token
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertmanagerConfig {
+    pub name: String,
+    // Base URL, alerts are posted to <url>/api/v2/alerts
+    pub url: String,
+    // How often firing alerts are re-sent so Alertmanager does not time them out
+    #[serde(default = "default_resend_interval")]
+    pub resend_interval: String,
+    // Static labels added to every alert, e.g. { cluster = "homelab" }
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    // Link shown as the alert source, e.g. the dashboard URL
+    #[serde(default)]
+    pub generator_url: Option<String>,
+}
+
+impl AlertmanagerConfig {
+    pub fn resend_interval(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.resend_interval)
+            .with_context(|| format!("alerting.alertmanagers.{}.resend_interval", self.name))
+    }
+}
+
+fn default_resend_interval() -> String {
+    "1m".to_string()
+}
+
+impl Default for AlertingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: "30s".to_string(),
+            pending: "2m".to_string(),
+            hysteresis: 2.0,
+            webhook_timeout: "10s".to_string(),
+            webhooks: Vec::new(),
+            alertmanagers: Vec::new(),
+        }
+    }
+}
+
+impl Default for ThresholdsConfig {
+    fn default() -> Self {
+        Self {
+            hwmon_limits: true,
+            warning: None,
+            critical: None,
+            groups: Vec::new(),
+            sensors: Vec::new(),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            default_ttl: "15s".to_string(),
+            ttls: [("1m", "15s"), ("1h", "1m"), ("1d", "5m")]
+                .into_iter()
+                .map(|(window, ttl)| (window.to_string(), ttl.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl Default for NodesConfig {
+    fn default() -> Self {
+        Self {
+            offline_after: "5m".to_string(),
+            last_seen_lookback: "1h".to_string(),
+            exporter_port: 9100,
+            exporter_pods: ".*node-exporter.*".to_string(),
+            mapping_file: None,
+            refresh_interval: "1m".to_string(),
+        }
+    }
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            interval: "15s".to_string(),
+            keep_alive: "15s".to_string(),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: "0.0.0.0:3000".to_string(),
+            request_deadline: "30s".to_string(),
+            max_staleness: "10m".to_string(),
+            window_deadline: "25s".to_string(),
+        }
+    }
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://vmsingle-vm-victoria-metrics-k8s-stack.victoria-metrics.svc:8429".to_string(),
+            connect_timeout: "5s".to_string(),
+            request_timeout: "20s".to_string(),
+            max_retries: 2,
+            retry_backoff: "200ms".to_string(),
+            max_retry_backoff: "2s".to_string(),
+            pool_idle_timeout: "90s".to_string(),
+        }
+    }
+}
+
+impl Default for DatasourcesConfig {
+    fn default() -> Self {
+        Self {
+            default: "default".to_string(),
+            allow_selection: false,
+            sources: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for FederationConfig {
+    fn default() -> Self {
+        Self {
+            clusters: Vec::new(),
+            cluster_deadline: "28s".to_string(),
+        }
+    }
+}
+
+impl Default for QueryConfig {
+    fn default() -> Self {
+        Self {
+            metric: "node_hwmon_temp_celsius".to_string(),
+            selectors: BTreeMap::new(),
+            windows: WindowsConfig::default(),
+            max_window: "30d".to_string(),
+            matchers: Vec::new(),
+        }
+    }
+}
+
+impl Default for WindowsConfig {
+    fn default() -> Self {
+        Self {
+            minutely: "1m".to_string(),
+            hourly: "1h".to_string(),
+            daily: "1d".to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration file (if present), applies environment overrides and validates the result.
+    pub fn load() -> anyhow::Result<Self> {
+        let explicit_path = std::env::var(format!("{}CONFIG", ENV_PREFIX)).ok();
+        let path = explicit_path.clone().unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
+
+        let mut config = if Path::new(&path).exists() {
+            info!("Loading configuration from {}", path);
+            let contents = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read config file {}", path))?;
+            toml::from_str(&contents).with_context(|| format!("failed to parse config file {}", path))?
+        } else if explicit_path.is_some() {
+            bail!("config file {} does not exist", path);
+        } else {
+            info!("No config file at {}, using defaults", path);
+            Config::default()
+        };
+
+        config.apply_env_overrides(|key| std::env::var(format!("{}{}", ENV_PREFIX, key)).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply_env_overrides(&mut self, var: impl Fn(&str) -> Option<String>) -> anyhow::Result<()> {
+        if let Some(value) = var("LISTEN_ADDR") {
+            self.server.listen_addr = value;
+        }
+        if let Some(value) = var("REQUEST_DEADLINE") {
+            self.server.request_deadline = value;
+        }
+        if let Some(value) = var("MAX_STALENESS") {
+            self.server.max_staleness = value;
+        }
+        if let Some(value) = var("UPSTREAM_URL") {
+            self.upstream.url = value;
+        }
+        if let Some(value) = var("DATASOURCE") {
+            self.datasources.default = value;
+        }
+        if let Some(value) = var("FEDERATION_CLUSTERS") {
+            self.federation.clusters = value
+                .split(',')
+                .map(str::trim)
+                .filter(|cluster| !cluster.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Some(value) = var("ALLOW_DATASOURCE_SELECTION") {
+            self.datasources.allow_selection = value.parse().with_context(|| {
+                format!("{}ALLOW_DATASOURCE_SELECTION: expected true or false, got {:?}", ENV_PREFIX, value)
+            })?;
+        }
+        if let Some(value) = var("CONNECT_TIMEOUT") {
+            self.upstream.connect_timeout = value;
+        }
+        if let Some(value) = var("REQUEST_TIMEOUT") {
+            self.upstream.request_timeout = value;
+        }
+        if let Some(value) = var("MAX_RETRIES") {
+            self.upstream.max_retries = value
+                .parse()
+                .with_context(|| format!("{}MAX_RETRIES: invalid number {:?}", ENV_PREFIX, value))?;
+        }
+        if let Some(value) = var("METRIC") {
+            self.query.metric = value;
+        }
+        // Comma separated list of label=value pairs, replaces the configured selectors
+        if let Some(value) = var("SELECTORS") {
+            self.query.selectors = parse_selectors(&value)?;
+        }
+        if let Some(value) = var("WINDOW_MINUTELY") {
+            self.query.windows.minutely = value;
+        }
+        if let Some(value) = var("WINDOW_HOURLY") {
+            self.query.windows.hourly = value;
+        }
+        if let Some(value) = var("WINDOW_DAILY") {
+            self.query.windows.daily = value;
+        }
+        if let Some(value) = var("ALERTING_ENABLED") {
+            self.alerting.enabled = value
+                .parse()
+                .with_context(|| format!("{}ALERTING_ENABLED: expected true or false, got {:?}", ENV_PREFIX, value))?;
+        }
+        if let Some(value) = var("CACHE_ENABLED") {
+            self.cache.enabled = value
+                .parse()
+                .with_context(|| format!("{}CACHE_ENABLED: expected true or false, got {:?}", ENV_PREFIX, value))?;
+        }
+        if let Some(value) = var("STREAM_INTERVAL") {
+            self.stream.interval = value;
+        }
+        if let Some(value) = var("OFFLINE_AFTER") {
+            self.nodes.offline_after = value;
+        }
+        if let Some(value) = var("NODE_MAPPING_FILE") {
+            self.nodes.mapping_file = Some(value);
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        self.listen_addr()?;
+        validate_url("upstream.url", &self.upstream.url)?;
+        if self.datasources.default.is_empty() {
+            bail!("datasources.default must not be empty");
+        }
+        for (name, url) in &self.datasources.sources {
+            validate_url(&format!("datasources.sources.{}", name), url)?;
+        }
+        if !self.federation.clusters.is_empty() {
+            let datasources = self.datasources();
+            for cluster in &self.federation.clusters {
+                if !datasources.contains_key(cluster) {
+                    bail!("federation.clusters: {} is not a configured datasource", cluster);
+                }
+            }
+            let cluster_deadline = self.cluster_deadline()?;
+            if cluster_deadline <= self.window_deadline()? || cluster_deadline >= self.request_deadline()? {
+                bail!("federation.cluster_deadline must be between server.window_deadline and server.request_deadline");
+            }
+        }
+        self.request_deadline()?;
+        self.max_staleness()?;
+        if self.window_deadline()? >= self.request_deadline()? {
+            bail!("server.window_deadline must be below server.request_deadline");
+        }
+        self.connect_timeout()?;
+        self.request_timeout()?;
+        self.retry_backoff()?;
+        self.max_retry_backoff()?;
+        self.pool_idle_timeout()?;
+
+        if !is_valid_metric_name(&self.query.metric) {
+            bail!("query.metric: invalid metric name {:?}", self.query.metric);
+        }
+        for name in self.query.selectors.keys() {
+            if !is_valid_label_name(name) {
+                bail!("query.selectors: invalid label name {:?}", name);
+            }
+        }
+        self.minutely_window()?;
+        self.hourly_window()?;
+        self.daily_window()?;
+        self.max_window()?;
+
+        validate_limits("thresholds", self.thresholds.warning, self.thresholds.critical)?;
+        for group in &self.thresholds.groups {
+            validate_limits(&format!("thresholds.groups.{}", group.name), group.warning, group.critical)?;
+        }
+        for (index, rule) in self.thresholds.sensors.iter().enumerate() {
+            validate_limits(&format!("thresholds.sensors[{}]", index), rule.warning, rule.critical)?;
+        }
+
+        self.alerting_interval()?;
+        self.alerting_pending()?;
+        self.webhook_timeout()?;
+        if !self.alerting.hysteresis.is_finite() || self.alerting.hysteresis < 0.0 {
+            bail!("alerting.hysteresis: must be a non-negative number of degrees");
+        }
+        for webhook in &self.alerting.webhooks {
+            validate_url(&format!("alerting.webhooks.{}.url", webhook.name), &webhook.url)?;
+        }
+        self.cache_default_ttl()?;
+        self.cache_ttls()?;
+        self.stream_interval()?;
+        self.stream_keep_alive()?;
+        self.offline_after()?;
+        self.last_seen_lookback()?;
+        self.node_refresh_interval()?;
+        if let Some(path) = &self.nodes.mapping_file {
+            crate::nodes::load_mapping_file(path).context("nodes.mapping_file")?;
+        }
+        for alertmanager in &self.alerting.alertmanagers {
+            let field = format!("alerting.alertmanagers.{}", alertmanager.name);
+            validate_url(&format!("{}.url", field), &alertmanager.url)?;
+            alertmanager.resend_interval()?;
+            for name in alertmanager.labels.keys() {
+                if !is_valid_label_name(name) {
+                    bail!("{}.labels: invalid label name {:?}", field, name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn listen_addr(&self) -> anyhow::Result<SocketAddr> {
+        self.server
+            .listen_addr
+            .parse()
+            .with_context(|| format!("server.listen_addr: invalid address {:?}", self.server.listen_addr))
+    }
+
+    pub fn request_deadline(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.server.request_deadline).context("server.request_deadline")
+    }
+
+    pub fn max_staleness(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.server.max_staleness).context("server.max_staleness")
+    }
+
+    pub fn window_deadline(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.server.window_deadline).context("server.window_deadline")
+    }
+
+    pub fn connect_timeout(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.upstream.connect_timeout).context("upstream.connect_timeout")
+    }
+
+    pub fn request_timeout(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.upstream.request_timeout).context("upstream.request_timeout")
+    }
+
+    pub fn max_window(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.query.max_window).context("query.max_window")
+    }
+
+    pub fn alerting_interval(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.alerting.interval).context("alerting.interval")
+    }
+
+    pub fn alerting_pending(&self) -> anyhow::Result<Duration> {
+        // A zero pending duration fires on the first evaluation above the threshold
+        parse_duration_or_zero(&self.alerting.pending).context("alerting.pending")
+    }
+
+    pub fn webhook_timeout(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.alerting.webhook_timeout).context("alerting.webhook_timeout")
+    }
+
+    pub fn cache_default_ttl(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.cache.default_ttl).context("cache.default_ttl")
+    }
+
+    /// Cache TTLs as (window, ttl) pairs ordered by window.
+    pub fn cache_ttls(&self) -> anyhow::Result<Vec<(Duration, Duration)>> {
+        let mut ttls = self
+            .cache
+            .ttls
+            .iter()
+            .map(|(window, ttl)| {
+                let field = format!("cache.ttls.{}", window);
+                Ok((
+                    parse_duration(window).with_context(|| field.clone())?,
+                    parse_duration(ttl).with_context(|| field)?,
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        ttls.sort();
+        Ok(ttls)
+    }
+
+    pub fn stream_interval(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.stream.interval).context("stream.interval")
+    }
+
+    pub fn stream_keep_alive(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.stream.keep_alive).context("stream.keep_alive")
+    }
+
+    pub fn offline_after(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.nodes.offline_after).context("nodes.offline_after")
+    }
+
+    pub fn last_seen_lookback(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.nodes.last_seen_lookback).context("nodes.last_seen_lookback")
+    }
+
+    pub fn node_refresh_interval(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.nodes.refresh_interval).context("nodes.refresh_interval")
+    }
+
+    pub fn retry_backoff(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.upstream.retry_backoff).context("upstream.retry_backoff")
+    }
+
+    pub fn max_retry_backoff(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.upstream.max_retry_backoff).context("upstream.max_retry_backoff")
+    }
+
+    pub fn pool_idle_timeout(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.upstream.pool_idle_timeout).context("upstream.pool_idle_timeout")
+    }
+
+    pub fn cluster_deadline(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.federation.cluster_deadline).context("federation.cluster_deadline")
+    }
+
+    /// Every selectable datasource by name; the default one uses `upstream.url` unless it is listed.
+    pub fn datasources(&self) -> BTreeMap<String, String> {
+        let mut datasources = self.datasources.sources.clone();
+        datasources
+            .entry(self.datasources.default.clone())
+            .or_insert_with(|| self.upstream.url.clone());
+        datasources
+    }
+
+    pub fn minutely_window(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.query.windows.minutely).context("query.windows.minutely")
+    }
+
+    pub fn hourly_window(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.query.windows.hourly).context("query.windows.hourly")
+    }
+
+    pub fn daily_window(&self) -> anyhow::Result<Duration> {
+        parse_duration(&self.query.windows.daily).context("query.windows.daily")
+    }
+
+    /// The configured metric with its label selectors, e.g. `node_hwmon_temp_celsius{job="node-exporter"}`.
+    pub fn temperature_selector(&self) -> Selector {
+        self.metric_selector(&self.query.metric)
+    }
+
+    /// Any node-exporter metric with the configured label selectors and the request's matchers.
+    pub fn metric_selector(&self, metric: &str) -> Selector {
+        Selector::new(metric)
+            .with_all(self.query.selectors.iter().map(|(name, value)| LabelMatcher::equal(name, value)))
+            .with_all(self.query.matchers.iter().cloned())
+    }
+
+    /// A copy of the configuration whose selectors also carry `matchers`, for one filtered request.
+    pub fn with_matchers(&self, matchers: Vec<LabelMatcher>) -> Self {
+        let mut config = self.clone();
+        config.query.matchers = matchers;
+        config
+    }
+
+    pub fn max_over_time_query(&self, window: Duration) -> Expr {
+        Stat::Max.over_time(self.temperature_selector(), window)
+    }
+
+    /// Series selector of the node-exporter pods in kube_pod_info.
+    pub fn exporter_pods_selector(&self) -> Selector {
+        Selector::new("kube_pod_info").with(LabelMatcher::new("pod", MatchOp::Regex, &self.nodes.exporter_pods))
+    }
+
+    /// Timestamp of the newest temperature sample per instance within `nodes.last_seen_lookback`.
+    pub fn last_seen_query(&self) -> anyhow::Result<Expr> {
+        let timestamps = Function::Timestamp.apply(self.temperature_selector());
+        let newest = RangeFunction::Max.apply(timestamps.subquery(self.last_seen_lookback()?, LAST_SEEN_STEP));
+        Ok(Aggregation::Max.by(&["instance"], newest))
+    }
+
+    /// Instances with a temperature sample within `window`, including ones that have gone silent since.
+    pub fn instances_query(&self, window: Duration) -> Expr {
+        Aggregation::Count.by(&["instance"], RangeFunction::Last.apply(self.temperature_selector().range(window)))
+    }
+
+    /// Hottest sensor per instance over each `window`, used for range queries.
+    pub fn history_query(&self, window: Duration) -> Expr {
+        Aggregation::Max.by(&["instance"], self.max_over_time_query(window))
+    }
+}
+
+fn validate_url(field: &str, value: &str) -> anyhow::Result<()> {
+    let url = Url::parse(value).with_context(|| format!("{}: invalid URL {:?}", field, value))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        bail!("{}: unsupported scheme {:?}", field, url.scheme());
+    }
+    Ok(())
+}
+
+fn validate_limits(field: &str, warning: Option<f64>, critical: Option<f64>) -> anyhow::Result<()> {
+    for value in [warning, critical].into_iter().flatten() {
+        if !value.is_finite() {
+            bail!("{}: thresholds must be finite", field);
+        }
+    }
+    if let (Some(warning), Some(critical)) = (warning, critical) {
+        if warning >= critical {
+            bail!("{}: warning ({}) must be below critical ({})", field, warning, critical);
+        }
+    }
+    Ok(())
+}
+
+fn parse_selectors(value: &str) -> anyhow::Result<BTreeMap<String, String>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid selector {:?}, expected label=value", pair))?;
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Parses a Prometheus-style duration such as `30s`, `5m`, `1h30m` or `7d`.
+pub fn parse_duration(value: &str) -> anyhow::Result<Duration> {
+    let duration = parse_duration_or_zero(value)?;
+    if duration.is_zero() {
+        bail!("duration {:?} must be positive", value);
+    }
+    Ok(duration)
+}
+
+/// Like `parse_duration`, but zero is accepted in any spelling (`0`, `0s`, `0m`, `00s`).
+fn parse_duration_or_zero(value: &str) -> anyhow::Result<Duration> {
+    if value.is_empty() {
+        bail!("empty duration");
+    }
+    // A bare zero needs no unit
+    if value.chars().all(|c| c == '0') {
+        return Ok(Duration::ZERO);
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if digits == 0 {
+            bail!("invalid duration {:?}", value);
+        }
+        let amount: u64 = rest[..digits].parse().with_context(|| format!("invalid duration {:?}", value))?;
+        rest = &rest[digits..];
+
+        let unit_len = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let part = match &rest[..unit_len] {
+            "ms" => Some(Duration::from_millis(amount)),
+            "s" => Some(Duration::from_secs(amount)),
+            "m" => amount.checked_mul(60).map(Duration::from_secs),
+            "h" => amount.checked_mul(60 * 60).map(Duration::from_secs),
+            "d" => amount.checked_mul(24 * 60 * 60).map(Duration::from_secs),
+            "w" => amount.checked_mul(7 * 24 * 60 * 60).map(Duration::from_secs),
+            "y" => amount.checked_mul(365 * 24 * 60 * 60).map(Duration::from_secs),
+            unit => bail!("invalid duration unit {:?} in {:?}", unit, value),
+        };
+        // Durations come from request parameters too, so an overflow is an error rather than a panic
+        total = part
+            .and_then(|part| total.checked_add(part))
+            .with_context(|| format!("duration {:?} is too long", value))?;
+        rest = &rest[unit_len..];
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(90 * 60));
+        assert_eq!(parse_duration("7d").unwrap(), Duration::from_secs(7 * 24 * 60 * 60));
+        assert_eq!(parse_duration("1w1y").unwrap(), Duration::from_secs((7 + 365) * 24 * 60 * 60));
+
+        for invalid in ["", "5", "m", "5x", "0s", "-1s", "1.5h"] {
+            assert!(parse_duration(invalid).is_err(), "{:?} should be rejected", invalid);
+        }
+        // Request parameters must not be able to overflow the arithmetic
+        assert!(parse_duration("999999999999999999y").is_err());
+        assert!(parse_duration("18446744073709551615s1s").is_err());
+    }
+
+    #[test]
+    fn pending_accepts_any_zero() {
+        let pending = |value: &str| {
+            let mut config = Config::default();
+            config.alerting.pending = value.to_string();
+            config.alerting_pending()
+        };
+
+        for zero in ["0", "0s", "0m", "00s", "0h0m"] {
+            assert_eq!(pending(zero).unwrap(), Duration::ZERO, "{:?}", zero);
+        }
+        assert_eq!(pending("2m").unwrap(), Duration::from_secs(120));
+        assert!(pending("0x").is_err());
+        assert!(pending("").is_err());
+    }
+
+    #[test]
+    fn environment_overrides_settings() {
+        let vars = HashMap::from([
+            ("LISTEN_ADDR", "127.0.0.1:8080"),
+            ("UPSTREAM_URL", "http://vm:8429"),
+            ("MAX_RETRIES", "5"),
+            ("SELECTORS", "job=node-exporter,cluster=homelab"),
+            ("WINDOW_HOURLY", "2h"),
+            ("FEDERATION_CLUSTERS", "east, west,"),
+            ("ALLOW_DATASOURCE_SELECTION", "true"),
+        ]);
+        let mut config = Config::default();
+
+        config.apply_env_overrides(|key| vars.get(key).map(|value| value.to_string())).unwrap();
+
+        assert_eq!(config.server.listen_addr, "127.0.0.1:8080");
+        assert_eq!(config.upstream.url, "http://vm:8429");
+        assert_eq!(config.upstream.max_retries, 5);
+        assert_eq!(config.query.selectors.len(), 2);
+        assert_eq!(config.query.selectors["cluster"], "homelab");
+        assert_eq!(config.query.windows.hourly, "2h");
+        assert_eq!(config.federation.clusters, ["east", "west"]);
+        assert!(config.datasources.allow_selection);
+
+        let invalid = |key: &'static str, value: &'static str| {
+            Config::default().apply_env_overrides(|name| (name == key).then(|| value.to_string())).is_err()
+        };
+        assert!(invalid("MAX_RETRIES", "many"));
+        assert!(invalid("CACHE_ENABLED", "yes"));
+    }
+
+    #[test]
+    fn validation_rejects_inconsistent_settings() {
+        assert!(Config::default().validate().is_ok());
+
+        let invalid = |change: fn(&mut Config)| {
+            let mut config = Config::default();
+            change(&mut config);
+            config.validate().is_err()
+        };
+        assert!(invalid(|config| config.server.listen_addr = "localhost".to_string()));
+        assert!(invalid(|config| config.upstream.url = "vm:8429".to_string()));
+        assert!(invalid(|config| config.server.window_deadline = "1m".to_string()));
+        assert!(invalid(|config| config.query.windows.daily = "1 day".to_string()));
+        assert!(invalid(|config| config.query.metric = "node-temp".to_string()));
+        assert!(invalid(|config| {
+            config.thresholds.warning = Some(90.0);
+            config.thresholds.critical = Some(80.0);
+        }));
+        assert!(invalid(|config| config.federation.clusters.push("east".to_string())));
+    }
+}
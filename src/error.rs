@@ -0,0 +1,183 @@
+use crate::backend::{is_timeout, QueryError};
+use axum::extract::rejection::QueryRejection;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+
+const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Error returned by the HTTP handlers, rendered as an RFC 7807 `application/problem+json` body.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiError {
+    // Invalid query parameters
+    BadRequest(String),
+    // A valid request the configuration does not permit
+    Forbidden(String),
+    NotFound(String),
+    // The request matches several things where it has to name one, e.g. a node of several federated clusters
+    Conflict(String),
+    // Connection failures and 5xx responses from the upstream
+    UpstreamUnreachable(String),
+    // The request or window deadline passed, or the upstream timed out
+    UpstreamTimeout(String),
+    // The upstream answered with a Prometheus error body
+    Query(QueryError),
+    // The upstream is down and the last good data is older than the maximum staleness
+    StaleDataExpired(Duration),
+    Internal(String),
+}
+
+/// Problem details object, see RFC 7807 section 3.
+#[derive(Debug, Serialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    // Prometheus errorType for query errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_type: Option<String>,
+}
+
+impl ApiError {
+    /// Classifies a failed upstream call.
+    pub fn upstream(error: &anyhow::Error) -> Self {
+        if let Some(query_error) = error.downcast_ref::<QueryError>() {
+            return Self::Query(query_error.clone());
+        }
+        if is_timeout(error) {
+            Self::UpstreamTimeout(format!("{:#}", error))
+        } else {
+            Self::UpstreamUnreachable(format!("{:#}", error))
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::UpstreamUnreachable(_) => StatusCode::BAD_GATEWAY,
+            Self::UpstreamTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            // A query the upstream refuses to parse was built from the request parameters
+            Self::Query(error) if error.error_type == "bad_data" => StatusCode::BAD_REQUEST,
+            Self::Query(error) if error.error_type == "timeout" => StatusCode::GATEWAY_TIMEOUT,
+            Self::Query(_) => StatusCode::BAD_GATEWAY,
+            Self::StaleDataExpired(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable identifier clients can match on, used as the problem type URI.
+    pub fn slug(&self) -> &'static str {
+        match self {
+            Self::BadRequest(_) => "bad-request",
+            Self::Forbidden(_) => "forbidden",
+            Self::NotFound(_) => "not-found",
+            Self::Conflict(_) => "conflict",
+            Self::UpstreamUnreachable(_) => "upstream-unreachable",
+            Self::UpstreamTimeout(_) => "upstream-timeout",
+            Self::Query(_) => "query-error",
+            Self::StaleDataExpired(_) => "stale-data-expired",
+            Self::Internal(_) => "internal-error",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Self::BadRequest(_) => "Invalid request parameters",
+            Self::Forbidden(_) => "Forbidden",
+            Self::NotFound(_) => "Not found",
+            Self::Conflict(_) => "Ambiguous request",
+            Self::UpstreamUnreachable(_) => "Metrics backend unreachable",
+            Self::UpstreamTimeout(_) => "Metrics backend timed out",
+            Self::Query(_) => "Metrics query failed",
+            Self::StaleDataExpired(_) => "Metrics backend unavailable",
+            Self::Internal(_) => "Internal server error",
+        }
+    }
+
+    fn problem(&self) -> Problem {
+        Problem {
+            problem_type: format!("urn:temperature-monitor:problem:{}", self.slug()),
+            title: self.title(),
+            status: self.status().as_u16(),
+            detail: self.to_string(),
+            error_type: match self {
+                Self::Query(error) => Some(error.error_type.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadRequest(detail)
+            | Self::Forbidden(detail)
+            | Self::NotFound(detail)
+            | Self::Conflict(detail)
+            | Self::UpstreamUnreachable(detail)
+            | Self::UpstreamTimeout(detail)
+            | Self::Internal(detail) => f.write_str(detail),
+            Self::Query(error) => f.write_str(&error.message),
+            Self::StaleDataExpired(age) => write!(
+                f,
+                "the upstream is unavailable and the last good data is {}s old",
+                age.as_secs()
+            ),
+        }
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::BadRequest(rejection.body_text())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = serde_json::to_string(&self.problem()).expect("problem details serialize to JSON");
+        (self.status(), [(header::CONTENT_TYPE, PROBLEM_JSON)], body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upstream_errors_are_classified() {
+        let query = anyhow::Error::new(QueryError {
+            error_type: "bad_data".to_string(),
+            message: "parse error".to_string(),
+        });
+        assert_eq!(ApiError::upstream(&query).status(), StatusCode::BAD_REQUEST);
+        assert_eq!(ApiError::upstream(&anyhow::anyhow!("connection refused")).status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn errors_render_as_problem_details() {
+        let error = ApiError::Query(QueryError {
+            error_type: "execution".to_string(),
+            message: "too many samples".to_string(),
+        });
+
+        let response = error.into_response();
+
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], PROBLEM_JSON);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["type"], "urn:temperature-monitor:problem:query-error");
+        assert_eq!(body["status"], 502);
+        assert_eq!(body["detail"], "too many samples");
+        assert_eq!(body["error_type"], "execution");
+    }
+}
@@ -0,0 +1,108 @@
+use crate::nodes::InstanceMapping;
+use crate::promql::{LabelMatcher, MatchOp};
+use crate::thresholds::glob_match;
+use regex::Regex;
+
+/// Node and label filters of `/api/temperatures`. Label matchers go into every selector as they are; node names
+/// are not a label, so node filters become a matcher on the instances that resolve to a matching node.
+#[derive(Debug, Default)]
+pub struct Filters {
+    // Glob patterns, a node matching any of them is kept
+    nodes: Vec<String>,
+    // Anchored like PromQL regex matchers
+    node_regex: Option<Regex>,
+    labels: Vec<LabelMatcher>,
+}
+
+impl Filters {
+    /// Parses the comma separated `node` globs and `labels` matchers and the `node_regex` parameter.
+    pub fn parse(nodes: Option<&str>, node_regex: Option<&str>, labels: Option<&str>) -> Result<Self, String> {
+        let split = |list: Option<&str>| -> Vec<String> {
+            list.into_iter()
+                .flat_map(|list| list.split(','))
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        let node_regex = node_regex
+            .map(|pattern| Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| format!("invalid node_regex: {}", e)))
+            .transpose()?;
+        let labels = split(labels)
+            .iter()
+            .map(|matcher| LabelMatcher::parse(matcher))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            nodes: split(nodes),
+            node_regex,
+            labels,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.filters_nodes() && self.labels.is_empty()
+    }
+
+    /// Whether only some nodes were asked for.
+    pub fn filters_nodes(&self) -> bool {
+        !self.nodes.is_empty() || self.node_regex.is_some()
+    }
+
+    pub fn labels(&self) -> &[LabelMatcher] {
+        &self.labels
+    }
+
+    pub fn matches_node(&self, node: &str) -> bool {
+        let glob = self.nodes.is_empty() || self.nodes.iter().any(|pattern| glob_match(pattern, node));
+        glob && self.node_regex.as_ref().is_none_or(|regex| regex.is_match(node))
+    }
+
+    /// Matchers to add to every query: the label matchers, plus one selecting the instances of matching nodes
+    /// when nodes are filtered.
+    pub fn matchers(&self, instances: &[InstanceMapping]) -> Vec<LabelMatcher> {
+        let mut matchers = self.labels.clone();
+        if self.filters_nodes() {
+            let instances: Vec<String> = instances
+                .iter()
+                .filter(|mapping| self.matches_node(&mapping.node))
+                .map(|mapping| regex::escape(&mapping.instance))
+                .collect();
+            // An empty alternation matches no instance, so no temperatures are returned for unknown nodes
+            matchers.push(LabelMatcher::new("instance", MatchOp::Regex, instances.join("|")));
+        }
+        matchers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nodes::Source;
+
+    fn mapping(instance: &str, node: &str) -> InstanceMapping {
+        InstanceMapping {
+            instance: instance.to_string(),
+            node: node.to_string(),
+            source: Source::Uname,
+        }
+    }
+
+    #[test]
+    fn node_filters_select_instances() {
+        let filters = Filters::parse(Some("blade0*"), Some(".*[13]"), Some("job=node-exporter")).unwrap();
+        let instances = [
+            mapping("10.0.0.1:9100", "blade001"),
+            mapping("10.0.0.2:9100", "blade002"),
+            mapping("10.0.0.3:9100", "blade003"),
+            mapping("10.0.1.1:9100", "blade101"),
+        ];
+
+        let matchers: Vec<String> = filters.matchers(&instances).iter().map(ToString::to_string).collect();
+
+        assert_eq!(
+            matchers,
+            [r#"job="node-exporter""#, r#"instance=~"10\\.0\\.0\\.1:9100|10\\.0\\.0\\.3:9100""#]
+        );
+        assert!(Filters::parse(None, Some("("), None).is_err());
+    }
+}
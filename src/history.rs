@@ -0,0 +1,304 @@
+use crate::error::ApiError;
+use crate::filters::Filters;
+use crate::state::AppState;
+use axum::{
+    extract::{rejection::QueryRejection, Query, State},
+    response::Json,
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::warn;
+
+// Prometheus rejects range queries returning more than 11000 points per series
+const MAX_POINTS_PER_SERIES: i64 = 11_000;
+// Resolution used when the client does not pass a step
+const DEFAULT_POINTS_PER_SERIES: i64 = 300;
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryParams {
+    // Only return the series of this node
+    node: Option<String>,
+    // RFC 3339 or unix timestamp, defaults to one day before `end`
+    start: Option<String>,
+    // RFC 3339 or unix timestamp, defaults to now
+    end: Option<String>,
+    // Duration such as 5m, or seconds
+    step: Option<String>,
+    // Name of a configured datasource, the default one when absent
+    datasource: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryResponse {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    step_seconds: i64,
+    series: Vec<NodeSeries>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NodeSeries {
+    node: String,
+    pub points: Vec<HistoryPoint>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryPoint {
+    timestamp: i64,
+    temperature: f64,
+}
+
+pub async fn get_temperature_history(
+    State(state): State<AppState>,
+    params: Result<Query<HistoryParams>, QueryRejection>,
+) -> Result<Json<HistoryResponse>, ApiError> {
+    let Query(params) = params?;
+    tokio::time::timeout(state.request_deadline, fetch_history(&state, &params))
+        .await
+        .map_err(|_| {
+            warn!("History request exceeded deadline of {:?}", state.request_deadline);
+            ApiError::UpstreamTimeout(format!("no answer within the request deadline of {:?}", state.request_deadline))
+        })?
+        .map(Json)
+}
+
+pub async fn fetch_history(state: &AppState, params: &HistoryParams) -> Result<HistoryResponse, ApiError> {
+    let end = match &params.end {
+        Some(value) => parse_timestamp(value)?,
+        None => Utc::now(),
+    };
+    let start = match &params.start {
+        Some(value) => parse_timestamp(value)?,
+        None => end - Duration::days(1),
+    };
+    if start >= end {
+        warn!("Rejecting history request with start {} not before end {}", start, end);
+        return Err(ApiError::BadRequest(format!("start {} is not before end {}", start, end)));
+    }
+
+    let range_seconds = (end - start).num_seconds();
+    let step_seconds = match &params.step {
+        Some(value) => parse_step(value)?,
+        None => (range_seconds / DEFAULT_POINTS_PER_SERIES).max(60),
+    };
+    let points = range_seconds / step_seconds;
+    if points > MAX_POINTS_PER_SERIES {
+        warn!("Rejecting history request with {} points per series", points);
+        return Err(ApiError::BadRequest(format!(
+            "{} points per series exceed the maximum of {}, use a larger step",
+            points, MAX_POINTS_PER_SERIES
+        )));
+    }
+
+    let series = node_series(state, params.datasource.as_deref(), params.node.as_deref(), start, end, step_seconds).await?;
+    if let Some(node) = params.node.as_ref().filter(|_| series.is_empty()) {
+        return Err(ApiError::NotFound(format!("no temperature history for node {}", node)));
+    }
+
+    Ok(HistoryResponse {
+        start,
+        end,
+        step_seconds,
+        series,
+    })
+}
+
+/// Hottest temperature per node and step between `start` and `end`, optionally for a single node.
+pub async fn node_series(
+    state: &AppState,
+    datasource: Option<&str>,
+    node: Option<&str>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    step_seconds: i64,
+) -> Result<Vec<NodeSeries>, ApiError> {
+    let datasource = state.datasource(datasource)?;
+    let node_map = datasource.nodes.map().await;
+
+    // For a single node only its instances are queried, including ones that went silent within the range
+    let filtered_config;
+    let config = match node {
+        Some(node) => {
+            let internal = |e: anyhow::Error| ApiError::Internal(format!("{:#}", e));
+            let (daily, max_window) = (state.config.daily_window().map_err(internal)?, state.config.max_window().map_err(internal)?);
+            // Reaching back to the start of the range, within the bounds of what ?windows= may query
+            let lookback = (Utc::now() - start).to_std().unwrap_or_default().clamp(daily, max_window.max(daily));
+            let instances = datasource.nodes.instances_within(lookback).await;
+            let filters = Filters::parse(None, Some(&regex::escape(node)), None).expect("an escaped name is a valid regex");
+            filtered_config = state.config.with_matchers(filters.matchers(&instances));
+            &filtered_config
+        }
+        None => state.config.as_ref(),
+    };
+
+    // Hottest sensor per instance, using the maximum within each step so short spikes are not skipped
+    let step = std::time::Duration::from_secs(step_seconds as u64);
+    let query = config.history_query(step).to_string();
+    let results = datasource.backend.range_query(&query, start, end, step).await.map_err(|e| {
+        warn!("Failed to fetch temperature history: {}", e);
+        ApiError::upstream(&e)
+    })?;
+
+    // Several instances may resolve to the same node; keep the maximum per timestamp
+    let mut nodes: BTreeMap<String, BTreeMap<i64, f64>> = BTreeMap::new();
+    for series in results {
+        let Some(instance) = series.metric.get("instance") else {
+            continue;
+        };
+        let name = node_map.node_for(instance);
+        if node.is_some_and(|wanted| wanted != name) {
+            continue;
+        }
+
+        let points = nodes.entry(name).or_default();
+        for (timestamp, value) in series.values {
+            let point = points.entry(timestamp as i64).or_insert(f64::NEG_INFINITY);
+            *point = point.max(value);
+        }
+    }
+
+    Ok(nodes
+        .into_iter()
+        .map(|(node, points)| NodeSeries {
+            node,
+            points: points
+                .into_iter()
+                .map(|(timestamp, value)| HistoryPoint {
+                    timestamp,
+                    temperature: (value * 10.0).round() / 10.0,
+                })
+                .collect(),
+        })
+        .collect())
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, ApiError> {
+    // "NaN" and "inf" parse as floats too, but are no point in time
+    if let Some(seconds) = value.parse::<f64>().ok().filter(|seconds| seconds.is_finite()) {
+        if let Some(timestamp) = Utc.timestamp_opt(seconds as i64, 0).single() {
+            return Ok(timestamp);
+        }
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|_| {
+            warn!("Invalid timestamp {:?}", value);
+            ApiError::BadRequest(format!("invalid timestamp {:?}, expected RFC 3339 or unix seconds", value))
+        })
+}
+
+fn parse_step(value: &str) -> Result<i64, ApiError> {
+    let seconds = match value.parse::<i64>() {
+        Ok(seconds) => seconds,
+        Err(_) => crate::config::parse_duration(value)
+            .map(|duration| duration.as_secs() as i64)
+            .map_err(|e| {
+                warn!("Invalid step {:?}: {}", value, e);
+                ApiError::BadRequest(format!("invalid step {:?}: {}", value, e))
+            })?,
+    };
+    if seconds < 1 {
+        warn!("Invalid step {:?}", value);
+        return Err(ApiError::BadRequest(format!("step {:?} must be at least one second", value)));
+    }
+    Ok(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory::{sample, InMemoryBackend};
+    use crate::backend::RangeSeries;
+    use crate::test_support::{pod, state_with, NODE_EXPORTER_PODS};
+    use std::collections::HashMap;
+
+    fn series(instance: &str, values: &[(f64, f64)]) -> RangeSeries {
+        RangeSeries {
+            metric: HashMap::from([("instance".to_string(), instance.to_string())]),
+            values: values.to_vec(),
+        }
+    }
+
+    fn params(node: Option<&str>) -> HistoryParams {
+        HistoryParams {
+            node: node.map(str::to_string),
+            start: Some("1700000000".to_string()),
+            end: Some("2023-11-14T23:13:20Z".to_string()),
+            step: Some("5m".to_string()),
+            datasource: None,
+        }
+    }
+
+    fn backend() -> InMemoryBackend {
+        InMemoryBackend::new()
+            .with_series(
+                NODE_EXPORTER_PODS,
+                vec![
+                    pod("node-exporter-a", "10.0.0.1", "blade001"),
+                    pod("node-exporter-b", "10.0.0.2", "blade002"),
+                ],
+            )
+            .with_range(
+                "max by (instance) (max_over_time(node_hwmon_temp_celsius[5m]))",
+                vec![
+                    series("10.0.0.1:9100", &[(1700000000.0, 70.04), (1700000300.0, 71.56)]),
+                    series("10.0.0.2:9100", &[(1700000000.0, 55.0)]),
+                ],
+            )
+            // A single node is queried through its instances, looked up as far back as `query.max_window`
+            .with_instant(
+                "count by (instance) (last_over_time(node_hwmon_temp_celsius[30d]))",
+                vec![sample(&[("instance", "10.0.0.1:9100")], 1.0), sample(&[("instance", "10.0.0.2:9100")], 1.0)],
+            )
+            .with_range(
+                r#"max by (instance) (max_over_time(node_hwmon_temp_celsius{instance=~"10\\.0\\.0\\.2:9100"}[5m]))"#,
+                vec![series("10.0.0.2:9100", &[(1700000000.0, 55.0)])],
+            )
+    }
+
+    #[tokio::test]
+    async fn history_groups_series_by_node() {
+        let response = fetch_history(&state_with(backend()), &params(None)).await.unwrap();
+
+        assert_eq!(response.step_seconds, 300);
+        assert_eq!(response.series.len(), 2);
+        assert_eq!(response.series[0].node, "blade001");
+        assert_eq!(response.series[0].points.len(), 2);
+        assert_eq!(response.series[0].points[1].timestamp, 1700000300);
+        assert_eq!(response.series[0].points[1].temperature, 71.6);
+    }
+
+    #[tokio::test]
+    async fn history_filters_by_node() {
+        let response = fetch_history(&state_with(backend()), &params(Some("blade002"))).await.unwrap();
+
+        assert_eq!(response.series.len(), 1);
+        assert_eq!(response.series[0].node, "blade002");
+    }
+
+    #[tokio::test]
+    async fn history_rejects_inverted_range() {
+        let mut inverted = params(None);
+        inverted.start = Some("1700003600".to_string());
+
+        let result = fetch_history(&state_with(backend()), &inverted).await;
+
+        assert!(matches!(result.unwrap_err(), ApiError::BadRequest(_)));
+    }
+
+    #[test]
+    fn timestamps_must_be_finite() {
+        assert_eq!(parse_timestamp("1700000000.5").unwrap().timestamp(), 1700000000);
+        for value in ["NaN", "nan", "inf", "-infinity"] {
+            assert!(matches!(parse_timestamp(value), Err(ApiError::BadRequest(_))), "{}", value);
+        }
+    }
+
+    #[tokio::test]
+    async fn history_of_unknown_node_is_not_found() {
+        let result = fetch_history(&state_with(backend()), &params(Some("blade404"))).await;
+
+        assert!(matches!(result.unwrap_err(), ApiError::NotFound(_)));
+    }
+}
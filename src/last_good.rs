@@ -0,0 +1,145 @@
+use crate::{QueryParams, TemperatureResponse};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Distinct parameter combinations remembered; the oldest is dropped beyond this
+const MAX_ENTRIES: usize = 64;
+
+/// Last successful `/api/temperatures` response per parameter combination, served while the upstream is down.
+pub struct LastGood {
+    max_staleness: Duration,
+    // Keyed on the parameters themselves, so no two combinations can collide
+    entries: Mutex<HashMap<QueryParams, (DateTime<Utc>, TemperatureResponse)>>,
+}
+
+/// Why no stale response could be served.
+#[derive(Debug, PartialEq)]
+pub enum Unavailable {
+    // Nothing was ever fetched successfully for these parameters
+    Missing,
+    // The last good response is older than the configured maximum staleness
+    Expired(Duration),
+}
+
+impl LastGood {
+    pub fn new(max_staleness: Duration) -> Self {
+        Self {
+            max_staleness,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, params: &QueryParams, response: &TemperatureResponse, at: DateTime<Utc>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES && !entries.contains_key(params) {
+            if let Some(oldest) = entries.iter().min_by_key(|(_, (at, _))| *at).map(|(key, _)| key.clone()) {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(params.clone(), (at, response.clone()));
+    }
+
+    /// The last good response marked as stale, unless it is missing or too old.
+    pub fn stale(&self, params: &QueryParams, now: DateTime<Utc>) -> Result<TemperatureResponse, Unavailable> {
+        let entries = self.entries.lock().unwrap();
+        let (at, response) = entries.get(params).ok_or(Unavailable::Missing)?;
+        let age = (now - *at).to_std().unwrap_or_default();
+        if age > self.max_staleness {
+            return Err(Unavailable::Expired(age));
+        }
+        Ok(TemperatureResponse {
+            stale: true,
+            data_age_seconds: Some(age.as_secs()),
+            ..response.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Detail;
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    fn node(node: &str) -> QueryParams {
+        QueryParams {
+            node: Some(node.to_string()),
+            ..QueryParams::default()
+        }
+    }
+
+    #[test]
+    fn oldest_entry_is_evicted_when_full() {
+        let last_good = LastGood::new(Duration::from_secs(3600));
+        let response = TemperatureResponse::default();
+        for index in 0..MAX_ENTRIES {
+            last_good.record(&node(&format!("blade{:03}", index)), &response, at(index as i64));
+        }
+        // Refreshing a remembered combination does not evict anything, and makes it the newest
+        last_good.record(&node("blade000"), &response, at(100));
+
+        last_good.record(&node("blade999"), &response, at(101));
+
+        assert!(last_good.stale(&node("blade000"), at(102)).is_ok());
+        assert_eq!(last_good.stale(&node("blade001"), at(102)).unwrap_err(), Unavailable::Missing);
+        assert!(last_good.stale(&node("blade002"), at(102)).is_ok());
+        assert!(last_good.stale(&node("blade999"), at(102)).is_ok());
+    }
+
+    #[test]
+    fn parameters_and_datasources_are_kept_apart() {
+        let last_good = LastGood::new(Duration::from_secs(3600));
+        let sensors = QueryParams {
+            detail: Some(Detail::Sensors),
+            ..QueryParams::default()
+        };
+        last_good.record(&sensors, &TemperatureResponse::default(), at(0));
+
+        let staging = QueryParams {
+            datasource: Some("staging".to_string()),
+            detail: Some(Detail::Sensors),
+            ..QueryParams::default()
+        };
+        assert!(last_good.stale(&sensors, at(1)).is_ok());
+        assert_eq!(last_good.stale(&QueryParams::default(), at(1)).unwrap_err(), Unavailable::Missing);
+        assert_eq!(last_good.stale(&staging, at(1)).unwrap_err(), Unavailable::Missing);
+        assert_eq!(last_good.stale(&node("blade001"), at(1)).unwrap_err(), Unavailable::Missing);
+    }
+
+    #[test]
+    fn separators_in_values_do_not_collide() {
+        let last_good = LastGood::new(Duration::from_secs(3600));
+        let regex = QueryParams {
+            node_regex: Some("r1|job=x".to_string()),
+            ..QueryParams::default()
+        };
+        last_good.record(&regex, &TemperatureResponse::default(), at(0));
+
+        let labels = QueryParams {
+            node_regex: Some("r1".to_string()),
+            labels: Some("job=x".to_string()),
+            ..QueryParams::default()
+        };
+        assert!(last_good.stale(&regex, at(1)).is_ok());
+        assert_eq!(last_good.stale(&labels, at(1)).unwrap_err(), Unavailable::Missing);
+    }
+
+    #[test]
+    fn responses_past_the_maximum_staleness_are_not_served() {
+        let last_good = LastGood::new(Duration::from_secs(600));
+        last_good.record(&QueryParams::default(), &TemperatureResponse::default(), at(0));
+
+        let stale = last_good.stale(&QueryParams::default(), at(600)).unwrap();
+        assert!(stale.stale);
+        assert_eq!(stale.data_age_seconds, Some(600));
+        assert_eq!(
+            last_good.stale(&QueryParams::default(), at(601)).unwrap_err(),
+            Unavailable::Expired(Duration::from_secs(601))
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::state::AppState;
+use crate::thresholds::Status;
+use crate::{fetch_temperatures, Detail, QueryParams, TemperatureMeasurement, TemperatureResponse};
+use axum::extract::{rejection::QueryRejection, Query, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, Notify};
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::{debug, info, warn};
+
+// Events buffered per subscriber before a slow client starts skipping
+const CHANNEL_CAPACITY: usize = 16;
+
+/// Update published by the live poller to every subscriber.
+#[derive(Debug, Clone)]
+pub enum LiveEvent {
+    Snapshot(Arc<TemperatureResponse>),
+    Status(StatusChange),
+}
+
+/// A node whose status differs from the previous snapshot.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatusChange {
+    pub node: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<String>,
+    pub status: Status,
+    // None for a node that was not part of the previous snapshot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_status: Option<Status>,
+    pub temperature: Option<f64>,
+    pub timestamp: DateTime<Utc>,
+}
+
+struct Snapshot {
+    taken: Instant,
+    response: Arc<TemperatureResponse>,
+}
+
+/// Fans the results of a single poller out to all streaming clients.
+pub struct LiveHub {
+    sender: broadcast::Sender<LiveEvent>,
+    latest: Mutex<Option<Snapshot>>,
+    // Wakes the poller when the first client subscribes
+    wake: Notify,
+    interval: Duration,
+    pub keep_alive: Duration,
+}
+
+impl LiveHub {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        Ok(Self {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            latest: Mutex::new(None),
+            wake: Notify::new(),
+            interval: config.stream_interval()?,
+            keep_alive: config.stream_keep_alive()?,
+        })
+    }
+
+    /// Subscribes to future events, returning the latest snapshot if it is still current.
+    pub fn subscribe(&self) -> (Option<Arc<TemperatureResponse>>, broadcast::Receiver<LiveEvent>) {
+        let receiver = self.sender.subscribe();
+        if self.sender.receiver_count() == 1 {
+            self.wake.notify_one();
+        }
+        let latest = self.latest.lock().unwrap();
+        let current = latest
+            .as_ref()
+            .filter(|snapshot| snapshot.taken.elapsed() < self.interval)
+            .map(|snapshot| snapshot.response.clone());
+        (current, receiver)
+    }
+
+    fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    /// Publishes a new snapshot together with the status changes since the previous one.
+    pub fn publish(&self, response: TemperatureResponse, now: DateTime<Utc>) {
+        let response = Arc::new(response);
+        let changes = {
+            let mut latest = self.latest.lock().unwrap();
+            let changes = match latest.as_ref() {
+                Some(previous) => status_changes(&previous.response.measurements, &response.measurements, now),
+                None => Vec::new(),
+            };
+            *latest = Some(Snapshot {
+                taken: Instant::now(),
+                response: response.clone(),
+            });
+            changes
+        };
+        // Sending only fails without subscribers, which is fine
+        for change in changes {
+            let _ = self.sender.send(LiveEvent::Status(change));
+        }
+        let _ = self.sender.send(LiveEvent::Snapshot(response));
+    }
+}
+
+fn status_changes(
+    previous: &[TemperatureMeasurement],
+    current: &[TemperatureMeasurement],
+    now: DateTime<Utc>,
+) -> Vec<StatusChange> {
+    let previous: HashMap<String, Status> = previous.iter().map(|m| (m.key(), m.status)).collect();
+    current
+        .iter()
+        .filter_map(|measurement| {
+            let previous_status = previous.get(&measurement.key()).copied();
+            (previous_status != Some(measurement.status)).then(|| StatusChange {
+                node: measurement.node.clone(),
+                cluster: measurement.cluster.clone(),
+                status: measurement.status,
+                previous_status,
+                temperature: measurement.minutely_temperature,
+                timestamp: now,
+            })
+        })
+        .collect()
+}
+
+/// Polls the temperatures every `stream.interval` while at least one client is subscribed.
+pub async fn run(state: AppState) {
+    let hub = state.hub.clone();
+    // Sensors are always fetched so subscribers can ask for them
+    let params = QueryParams {
+        detail: Some(Detail::Sensors),
+        ..QueryParams::default()
+    };
+    info!("Live stream polling every {:?} while clients are connected", hub.interval);
+
+    let mut ticker = tokio::time::interval(hub.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = hub.wake.notified() => {}
+        }
+        if !hub.has_subscribers() {
+            continue;
+        }
+
+        match tokio::time::timeout(state.request_deadline, fetch_temperatures(&state, &params)).await {
+            Ok(Ok(response)) => hub.publish(response, Utc::now()),
+            Ok(Err(error)) => warn!("Live update skipped, fetching temperatures failed: {}", error),
+            Err(_) => warn!("Live update skipped, fetching temperatures exceeded {:?}", state.request_deadline),
+        }
+        ticker.reset();
+    }
+}
+
+/// Receives hub events until the hub goes away; lagging clients skip what they missed.
+pub fn events(receiver: broadcast::Receiver<LiveEvent>) -> impl Stream<Item = LiveEvent> {
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((event, receiver)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("Live subscriber lagged behind, skipped {} event(s)", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct StreamParams {
+    // Set to `sensors` to keep the per-sensor breakdown in snapshots
+    detail: Option<Detail>,
+}
+
+pub async fn stream_temperatures(
+    State(state): State<AppState>,
+    params: Result<Query<StreamParams>, QueryRejection>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, ApiError> {
+    let Query(params) = params?;
+    let include_sensors = params.detail == Some(Detail::Sensors);
+    let (current, receiver) = state.hub.subscribe();
+    let stream = futures::stream::iter(current.map(LiveEvent::Snapshot))
+        .chain(events(receiver))
+        .map(move |event| match event {
+            LiveEvent::Snapshot(response) if include_sensors => Event::default().event("snapshot").json_data(&*response),
+            LiveEvent::Snapshot(response) => Event::default().event("snapshot").json_data(without_sensors(&response)),
+            LiveEvent::Status(change) => Event::default().event("status").json_data(&change),
+        });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(state.hub.keep_alive)))
+}
+
+fn without_sensors(response: &TemperatureResponse) -> TemperatureResponse {
+    TemperatureResponse {
+        status: response.status,
+        measurements: response
+            .measurements
+            .iter()
+            .map(|measurement| TemperatureMeasurement {
+                sensors: None,
+                ..measurement.clone()
+            })
+            .collect(),
+        stale: response.stale,
+        data_age_seconds: response.data_age_seconds,
+        errors: response.errors.clone(),
+        warnings: response.warnings.clone(),
+        cluster_errors: response.cluster_errors.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory::InMemoryBackend;
+    use crate::test_support::state_with;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn response(nodes: &[(&str, Status)]) -> TemperatureResponse {
+        TemperatureResponse {
+            status: nodes.iter().map(|(_, status)| *status).max().unwrap_or_default(),
+            measurements: nodes
+                .iter()
+                .map(|(node, status)| TemperatureMeasurement {
+                    node: node.to_string(),
+                    cluster: None,
+                    minutely_temperature: Some(60.0),
+                    hourly_temperature: Some(60.0),
+                    daily_temperature: Some(60.0),
+                    missing: Default::default(),
+                    last_seen: None,
+                    status: *status,
+                    windows: None,
+                    sensors: None,
+                })
+                .collect(),
+            ..TemperatureResponse::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn publish_broadcasts_snapshots_and_status_changes() {
+        let hub = LiveHub::new(&Config::default()).unwrap();
+        let (current, mut receiver) = hub.subscribe();
+        assert!(current.is_none());
+
+        let now = Utc::now();
+        hub.publish(response(&[("blade001", Status::Ok), ("blade002", Status::Ok)]), now);
+        assert!(matches!(receiver.recv().await.unwrap(), LiveEvent::Snapshot(_)));
+
+        hub.publish(response(&[("blade001", Status::Warning), ("blade002", Status::Ok)]), now);
+        match receiver.recv().await.unwrap() {
+            LiveEvent::Status(change) => {
+                assert_eq!(change.node, "blade001");
+                assert_eq!(change.status, Status::Warning);
+                assert_eq!(change.previous_status, Some(Status::Ok));
+            }
+            other => panic!("expected a status change, got {:?}", other),
+        }
+        assert!(matches!(receiver.recv().await.unwrap(), LiveEvent::Snapshot(_)));
+    }
+
+    #[tokio::test]
+    async fn late_subscribers_get_the_current_snapshot() {
+        let hub = LiveHub::new(&Config::default()).unwrap();
+        hub.publish(response(&[("blade001", Status::Ok)]), Utc::now());
+
+        let (current, _receiver) = hub.subscribe();
+
+        assert_eq!(current.unwrap().measurements[0].node, "blade001");
+    }
+
+    #[tokio::test]
+    async fn invalid_parameters_get_a_problem_response() {
+        let app = Router::new()
+            .route("/api/temperatures/stream", get(stream_temperatures))
+            .with_state(state_with(InMemoryBackend::new()));
+        let request = Request::get("/api/temperatures/stream?detail=everything").body(Body::empty()).unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["content-type"], "application/problem+json");
+    }
+}
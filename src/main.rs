@@ -1,268 +1,597 @@
+mod alerting;
+mod backend;
+mod config;
+mod error;
+mod filters;
+mod history;
+mod last_good;
+mod live;
+mod node_detail;
+mod nodes;
+mod presence;
+mod promql;
+mod sensors;
+mod state;
+mod thresholds;
+mod websocket;
+mod windows;
+#[cfg(test)]
+mod test_support;
+
 use axum::{
-    extract::Query,
-    http::StatusCode,
-    response::Json,
+    extract::{rejection::QueryRejection, Query, State},
+    http::header,
+    response::{IntoResponse, Json, Response},
     routing::get,
     Router,
 };
-use chrono::{Duration, Utc};
-use reqwest::Client;
+use backend::{InstantSample, InstantVector};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use config::Config;
+use error::ApiError;
+use filters::Filters;
+use last_good::Unavailable;
+use nodes::NodeMap;
+use presence::Presence;
+use promql::Expr;
+use sensors::{SensorKey, SensorLabels, SensorMeasurement};
+use state::{AppState, Datasource};
+use thresholds::{worst_judged, Headroom, Limits, Status, Thresholds};
+use windows::{
+    max_reading, merge_max, missing_windows, parse_window_stats, MissingReason, MissingWindows, Reading, WindowError,
+    WindowStat, WindowValues,
+};
+use std::collections::{BTreeSet, HashMap};
 use tower_http::cors::CorsLayer;
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 
-const VICTORIA_METRICS_URL: &str = "http://vmsingle-vm-victoria-metrics-k8s-stack.victoria-metrics.svc:8429";
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct TemperatureMeasurement {
+    node: String,
+    // Datasource the node was read from, set when `federation.clusters` is configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cluster: Option<String>,
+    // Null when the window has no value for the node, `missing` says why
+    minutely_temperature: Option<f64>,
+    hourly_temperature: Option<f64>,
+    daily_temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "MissingWindows::is_empty")]
+    missing: MissingWindows,
+    // Time of the node's newest temperature sample within `nodes.last_seen_lookback`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_seen: Option<DateTime<Utc>>,
+    // Worst status among the node's sensors that have limits, judged on the minutely reading
+    #[serde(default)]
+    status: Status,
+    // Requested ?windows= statistics, taken from the hottest sensor of the node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    windows: Option<WindowValues>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sensors: Option<Vec<SensorMeasurement>>,
+}
 
-#[derive(Debug, Serialize, Deserialize)]
-struct PrometheusResponse {
-    status: String,
-    data: PrometheusData,
+impl TemperatureMeasurement {
+    /// Identifies the node across clusters, `cluster/node` when federated.
+    fn key(&self) -> String {
+        match &self.cluster {
+            Some(cluster) => format!("{}/{}", cluster, self.node),
+            None => self.node.clone(),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct PrometheusData {
-    #[serde(rename = "resultType")]
-    result_type: String,
-    result: Vec<PrometheusResult>,
+#[derive(Debug, Clone, Default, Serialize)]
+struct TemperatureResponse {
+    // Worst status across all nodes
+    status: Status,
+    measurements: Vec<TemperatureMeasurement>,
+    // Set when the upstream failed and this is the last successful response
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stale: bool,
+    // How old stale data is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data_age_seconds: Option<u64>,
+    // Windows that failed; their values are null
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<WindowError>,
+    // Warnings returned by the upstream, e.g. about partial data
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+    // Federated clusters that could not be read; their nodes are missing from `measurements`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    cluster_errors: Vec<ClusterError>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct PrometheusResult {
-    metric: HashMap<String, String>,
-    value: (f64, String),
+/// A federated cluster whose temperatures could not be fetched.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct ClusterError {
+    cluster: String,
+    // Problem type and status the request would have failed with for this cluster alone
+    error_type: &'static str,
+    status: u16,
+    error: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct TemperatureMeasurement {
-    node: String,
-    minutely_temperature: f64,
-    hourly_temperature: f64,
-    daily_temperature: f64,
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize)]
+struct QueryParams {
+    // Name of a configured datasource, the default one when absent
+    datasource: Option<String>,
+    // Set to `sensors` to include every sensor of a node instead of only the hottest reading
+    detail: Option<Detail>,
+    // Comma separated extra windows such as `5m,6h,7d`, reported in a `windows` map per node
+    windows: Option<String>,
+    // Comma separated statistics for `windows`: max, min, avg or pNN (defaults to max)
+    stat: Option<String>,
+    // Comma separated node name globs such as `blade0*`
+    node: Option<String>,
+    // Regex the whole node name must match
+    node_regex: Option<String>,
+    // Comma separated label matchers such as `job=node-exporter,rack=~r1|r2`, added to every query
+    labels: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
-struct TemperatureResponse {
-    measurements: Vec<TemperatureMeasurement>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Detail {
+    Sensors,
 }
 
-#[derive(Debug, Deserialize)]
-struct QueryParams {
-    // Optional parameter to use localhost for development
-    #[serde(default)]
-    dev: bool,
+async fn get_temperatures(
+    State(state): State<AppState>,
+    params: Result<Query<QueryParams>, QueryRejection>,
+) -> Result<Response, ApiError> {
+    let Query(params) = params?;
+    // Bound the whole request, retries included, so a slow upstream cannot hang clients
+    let result = tokio::time::timeout(state.request_deadline, fetch_temperatures(&state, &params))
+        .await
+        .unwrap_or_else(|_| {
+            warn!("Temperature request exceeded deadline of {:?}", state.request_deadline);
+            Err(ApiError::UpstreamTimeout(format!(
+                "no answer within the request deadline of {:?}",
+                state.request_deadline
+            )))
+        });
+
+    match result {
+        Ok(response) => {
+            state.last_good.record(&params, &response, Utc::now());
+            Ok(Json(response).into_response())
+        }
+        // Upstream trouble: keep dashboards populated with the last good data for a while
+        Err(error) if error.status().is_server_error() => match state.last_good.stale(&params, Utc::now()) {
+            Ok(response) => {
+                let age = response.data_age_seconds.unwrap_or_default();
+                warn!("Serving {}s old temperatures after upstream failure ({})", age, error);
+                let warning = format!("110 - \"Response is Stale\" (upstream failed, data is {}s old)", age);
+                Ok(([(header::WARNING, warning)], Json(response)).into_response())
+            }
+            Err(Unavailable::Expired(age)) => {
+                warn!("Last good temperatures are {:?} old, exceeding the maximum staleness", age);
+                Err(ApiError::StaleDataExpired(age))
+            }
+            Err(Unavailable::Missing) => Err(error),
+        },
+        Err(error) => Err(error),
+    }
 }
 
-async fn get_temperatures(Query(params): Query<QueryParams>) -> Result<Json<TemperatureResponse>, StatusCode> {
-    let client = Client::new();
-    let base_url = if params.dev {
-        "http://localhost:8429"
-    } else {
-        VICTORIA_METRICS_URL
+async fn fetch_temperatures(state: &AppState, params: &QueryParams) -> Result<TemperatureResponse, ApiError> {
+    let clusters = &state.config.federation.clusters;
+    if clusters.is_empty() {
+        let datasource = state.datasource(params.datasource.as_deref())?;
+        return fetch_datasource(state, datasource, params).await;
+    }
+
+    // A named datasource narrows the federation down to that cluster
+    let clusters: Vec<&str> = match params.datasource.as_deref() {
+        Some(name) => {
+            state.datasource(Some(name))?;
+            vec![name]
+        }
+        None => clusters.iter().map(String::as_str).collect(),
+    };
+    let cluster_deadline = state.config.cluster_deadline().map_err(|e| ApiError::Internal(format!("{:#}", e)))?;
+    let responses = futures::future::join_all(clusters.into_iter().map(|cluster| async move {
+        let response = async {
+            let datasource = state.datasource(Some(cluster))?;
+            tokio::time::timeout(cluster_deadline, fetch_datasource(state, datasource, params))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(ApiError::UpstreamTimeout(format!("no answer within the cluster deadline of {:?}", cluster_deadline)))
+                })
+        };
+        (cluster, response.await)
+    }))
+    .await;
+    merge_clusters(responses)
+}
+
+// Tags every cluster's measurements, window errors and warnings with the cluster and merges them into one fleet
+// response; failed clusters are listed in `cluster_errors` unless every cluster failed
+fn merge_clusters(responses: Vec<(&str, Result<TemperatureResponse, ApiError>)>) -> Result<TemperatureResponse, ApiError> {
+    let mut merged = TemperatureResponse::default();
+    let mut failure = None;
+    let mut answered = false;
+    for (cluster, result) in responses {
+        match result {
+            Ok(response) => {
+                answered = true;
+                merged.measurements.extend(response.measurements.into_iter().map(|measurement| TemperatureMeasurement {
+                    cluster: Some(cluster.to_string()),
+                    ..measurement
+                }));
+                merged.errors.extend(response.errors.into_iter().map(|error| WindowError {
+                    cluster: Some(cluster.to_string()),
+                    ..error
+                }));
+                merged.warnings.extend(response.warnings.into_iter().map(|warning| format!("{}: {}", cluster, warning)));
+            }
+            Err(error) => {
+                warn!("Failed to fetch temperatures of cluster {}: {}", cluster, error);
+                merged.cluster_errors.push(ClusterError {
+                    cluster: cluster.to_string(),
+                    error_type: error.slug(),
+                    status: error.status().as_u16(),
+                    error: error.to_string(),
+                });
+                failure.get_or_insert(error);
+            }
+        }
+    }
+    if !answered {
+        warn!("Failed to fetch temperatures of every cluster");
+        return Err(failure.expect("failed clusters record their error"));
+    }
+
+    merged.measurements.sort_by(|a, b| (&a.cluster, &a.node).cmp(&(&b.cluster, &b.node)));
+    merged.status = worst_judged(merged.measurements.iter().map(|m| m.status));
+    Ok(merged)
+}
+
+async fn fetch_datasource(
+    state: &AppState,
+    datasource: &Datasource,
+    params: &QueryParams,
+) -> Result<TemperatureResponse, ApiError> {
+    let config = &state.config;
+    let backend = datasource.backend.as_ref();
+    // Configuration errors are caught at startup, so these only happen with a broken config in tests
+    let internal = |e: anyhow::Error| ApiError::Internal(format!("{:#}", e));
+    let filters = Filters::parse(params.node.as_deref(), params.node_regex.as_deref(), params.labels.as_deref())
+        .map_err(|e| {
+            warn!("Invalid filter parameter: {}", e);
+            ApiError::BadRequest(e)
+        })?;
+
+    let window_stats = match &params.windows {
+        Some(windows) => {
+            let max_window = config.max_window().map_err(internal)?;
+            parse_window_stats(windows, params.stat.as_deref(), max_window).map_err(|e| {
+                warn!("Invalid windows parameter: {}", e);
+                ApiError::BadRequest(e)
+            })?
+        }
+        None if params.stat.is_some() => {
+            warn!("Statistics requested without windows");
+            return Err(ApiError::BadRequest("stat requires windows".to_string()));
+        }
+        None => Vec::new(),
     };
 
     // Get current timestamp
     let now = Utc::now();
-    let _one_minute_ago = now - Duration::minutes(1);
-    let _one_hour_ago = now - Duration::hours(1);
-    let _one_day_ago = now - Duration::days(1);
 
     let mut blade_temperatures: HashMap<String, TemperatureMeasurement> = HashMap::new();
 
-    // First, get the pod IP to node name mapping
-    let ip_to_node_map = match get_pod_to_node_mapping(&client, base_url).await {
-        Ok(map) => map,
-        Err(e) => {
-            warn!("Failed to get pod to node mapping: {}, using IP-based naming", e);
-            HashMap::new()
+    // Which node each scraped instance belongs to, kept up to date in the background
+    let node_map = datasource.nodes.map().await;
+
+    // Filters are pushed into every selector, so only the selected series are fetched
+    let filtered_config;
+    let config = if filters.is_empty() {
+        config
+    } else {
+        // Node filters need every instance with data in the longest window, or silent nodes would lose theirs
+        let instances = if filters.filters_nodes() {
+            datasource.nodes.instances_within(config.daily_window().map_err(internal)?).await
+        } else {
+            Vec::new()
+        };
+        filtered_config = config.with_matchers(filters.matchers(&instances));
+        &filtered_config
+    };
+
+    // Query for minutely maximum (last 1 minute by default)
+    let minutely_query = config.max_over_time_query(config.minutely_window().map_err(internal)?);
+
+    // Query for hourly maximum (last 1 hour by default)
+    let hourly_query = config.max_over_time_query(config.hourly_window().map_err(internal)?);
+
+    // Query for daily maximum (last 1 day by default)
+    let daily_query = config.max_over_time_query(config.daily_window().map_err(internal)?);
+
+    // Sensor labels and chip names are only needed for the per-sensor breakdown and label-based thresholds
+    let include_sensors = params.detail == Some(Detail::Sensors);
+    let fetch_sensor_labels = async {
+        if include_sensors || config.thresholds.needs_sensor_labels() {
+            Some(SensorLabels::fetch(backend, config).await)
+        } else {
+            None
         }
     };
 
-    // Query for minutely maximum (last 1 minute)
-    let minutely_query = format!(
-        "max_over_time(node_hwmon_temp_celsius[1m])"
-    );
-    
-    // Query for hourly maximum (last 1 hour)
-    let hourly_query = format!(
-        "max_over_time(node_hwmon_temp_celsius[1h])"
-    );
-    
-    // Query for daily maximum (last 1 day)
-    let daily_query = format!(
-        "max_over_time(node_hwmon_temp_celsius[1d])"
-    );
+    // Every window is bounded on its own, so one slow query does not cost the others their results
+    let window_deadline = config.window_deadline().map_err(internal)?;
+    let offline_after = config.offline_after().map_err(internal)?;
+    let query_window = |query: Expr| async move {
+        tokio::time::timeout(window_deadline, backend.instant_query(&query.to_string()))
+            .await
+            .unwrap_or_else(|elapsed| Err(elapsed.into()))
+    };
+
+    // Custom window statistics requested with ?windows=
+    let selector = config.temperature_selector();
+    let fetch_custom = futures::future::join_all(window_stats.into_iter().map(|window_stat| {
+        let query = window_stat.stat.over_time(selector.clone(), window_stat.duration);
+        async move { (window_stat, query_window(query).await) }
+    }));
 
     // Fetch all three time ranges
-    let (minutely_result, hourly_result, daily_result) = tokio::try_join!(
-        fetch_prometheus_data(&client, base_url, &minutely_query),
-        fetch_prometheus_data(&client, base_url, &hourly_query),
-        fetch_prometheus_data(&client, base_url, &daily_query)
-    ).map_err(|e| {
-        warn!("Failed to fetch temperature data: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let (minutely_result, hourly_result, daily_result, custom_results, sensor_labels, thresholds, presence) = tokio::join!(
+        query_window(minutely_query),
+        query_window(hourly_query),
+        query_window(daily_query),
+        fetch_custom,
+        fetch_sensor_labels,
+        Thresholds::fetch(backend, config),
+        Presence::fetch(backend, config, &node_map)
+    );
+
+    // Keep every window that answered; failed ones are reported instead of failing the request
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    // Reported when no window answered at all
+    let mut failure = None;
+    let mut outcome = |window: &str, stat: Option<String>, result: anyhow::Result<InstantVector>| match result {
+        Ok(vector) => {
+            warnings.extend(vector.warnings);
+            Some(vector.samples)
+        }
+        Err(e) => {
+            warn!("Failed to fetch temperatures for window {}: {:#}", window, e);
+            errors.push(WindowError::new(window, stat, &e));
+            failure.get_or_insert_with(|| ApiError::upstream(&e));
+            None
+        }
+    };
+    let windows = &config.query.windows;
+    let results = WindowResults {
+        minutely: outcome(&windows.minutely, None, minutely_result),
+        hourly: outcome(&windows.hourly, None, hourly_result),
+        daily: outcome(&windows.daily, None, daily_result),
+        custom: custom_results
+            .into_iter()
+            .filter_map(|(window_stat, result)| {
+                let stat = Some(window_stat.stat.to_string());
+                outcome(&window_stat.window, stat, result).map(|samples| (window_stat, samples))
+            })
+            .collect(),
+    };
+    if results.minutely.is_none() && results.hourly.is_none() && results.daily.is_none() {
+        warn!("Failed to fetch temperature data for every window");
+        return Err(failure.expect("failed windows record their error"));
+    }
+    warnings.sort();
+    warnings.dedup();
 
     // Process results and group by blade server (using pod IP to node mapping)
-    process_temperature_data(minutely_result, hourly_result, daily_result, &mut blade_temperatures, &ip_to_node_map);
+    process_temperature_data(
+        results,
+        &mut blade_temperatures,
+        &node_map,
+        sensor_labels.as_ref(),
+        include_sensors,
+        &thresholds,
+    );
+    // Registered nodes can only be listed as offline when no label filter might exclude them
+    let presence = if filters.labels().is_empty() { presence } else { presence.without_known_nodes() };
+    presence.apply(&mut blade_temperatures, offline_after, now);
+    blade_temperatures.retain(|node, _| filters.matches_node(node));
 
     // Convert to vector and sort by node name
     let mut measurements: Vec<TemperatureMeasurement> = blade_temperatures.into_values().collect();
     measurements.sort_by(|a, b| a.node.cmp(&b.node));
 
-    Ok(Json(TemperatureResponse {
-        measurements,
-    }))
-}
-
+    // The fleet is as healthy as its worst node, nodes without limits do not hide the others
+    let status = worst_judged(measurements.iter().map(|m| m.status));
 
-
-async fn get_pod_to_node_mapping(
-    client: &Client,
-    base_url: &str,
-) -> Result<HashMap<String, String>, anyhow::Error> {
-    let url = format!("{}/api/v1/query", base_url);
-    let query = "kube_pod_info";
-    
-    let response = client
-        .get(&url)
-        .query(&[("query", query)])
-        .send()
-        .await?
-        .json::<PrometheusResponse>()
-        .await?;
-
-    if response.status != "success" {
-        return Err(anyhow::anyhow!("Prometheus query failed"));
-    }
-
-    let mut ip_to_node_map = HashMap::new();
-    
-    info!("Found {} kube_pod_info entries", response.data.result.len());
-    
-    for result in response.data.result {
-        // Only process node-exporter pods
-        if let Some(pod_name) = result.metric.get("pod") {
-            if pod_name.contains("node-exporter") {
-                if let (Some(pod_ip), Some(node)) = (
-                    result.metric.get("pod_ip"),
-                    result.metric.get("node")
-                ) {
-                    let instance = format!("{}:9100", pod_ip);
-                    info!("Mapping pod IP {} (instance: {}) to node: {}", pod_ip, instance, node);
-                    ip_to_node_map.insert(instance, node.clone());
-                }
-            }
-        }
-    }
-    
-    info!("Final ip_to_node_map has {} entries: {:?}", ip_to_node_map.len(), ip_to_node_map);
-
-    Ok(ip_to_node_map)
+    Ok(TemperatureResponse {
+        status,
+        measurements,
+        errors,
+        warnings,
+        ..TemperatureResponse::default()
+    })
 }
 
-async fn fetch_prometheus_data(
-    client: &Client,
-    base_url: &str,
-    query: &str,
-) -> Result<Vec<PrometheusResult>, anyhow::Error> {
-    let url = format!("{}/api/v1/query", base_url);
-    let response = client
-        .get(&url)
-        .query(&[("query", query)])
-        .send()
-        .await?
-        .json::<PrometheusResponse>()
-        .await?;
-
-    if response.status != "success" {
-        return Err(anyhow::anyhow!("Prometheus query failed"));
-    }
-
-    Ok(response.data.result)
+// Raw samples of the three standard windows and any ?windows= statistics, None for windows whose query failed
+struct WindowResults {
+    minutely: Option<Vec<InstantSample>>,
+    hourly: Option<Vec<InstantSample>>,
+    daily: Option<Vec<InstantSample>>,
+    custom: Vec<(WindowStat, Vec<InstantSample>)>,
 }
 
 fn process_temperature_data(
-    minutely: Vec<PrometheusResult>,
-    hourly: Vec<PrometheusResult>,
-    daily: Vec<PrometheusResult>,
+    results: WindowResults,
     blade_temperatures: &mut HashMap<String, TemperatureMeasurement>,
-    ip_to_node_map: &HashMap<String, String>,
+    node_map: &NodeMap,
+    sensor_labels: Option<&SensorLabels>,
+    include_sensors: bool,
+    thresholds: &Thresholds,
 ) {
     // Create lookup maps for faster access
-    let minutely_map: HashMap<String, f64> = minutely
+    let minutely_map = results.minutely.map(sensor_map);
+    let hourly_map = results.hourly.map(sensor_map);
+    let daily_map = results.daily.map(sensor_map);
+    let custom_maps: Vec<(WindowStat, HashMap<SensorKey, f64>)> = results
+        .custom
         .into_iter()
-        .filter_map(|result| {
-            let instance = result.metric.get("instance")?.clone();
-            let temp: f64 = result.value.1.parse().ok()?;
-            Some((instance, temp))
-        })
+        .map(|(window_stat, samples)| (window_stat, sensor_map(samples)))
         .collect();
 
-    let hourly_map: HashMap<String, f64> = hourly
-        .into_iter()
-        .filter_map(|result| {
-            let instance = result.metric.get("instance")?.clone();
-            let temp: f64 = result.value.1.parse().ok()?;
-            Some((instance, temp))
-        })
-        .collect();
+    // Aggregate temperatures by node; several instances (host and pod IP scrapes of one exporter, say) may resolve
+    // to the same node, and all of their sensors count towards it
+    let mut node_groups: HashMap<String, Vec<SensorReading>> = HashMap::new();
 
-    let daily_map: HashMap<String, f64> = daily
+    // Every sensor seen in any window, so blades missing from the short windows (offline, just booted) still show up
+    let sensors: BTreeSet<&SensorKey> = [&minutely_map, &hourly_map, &daily_map]
         .into_iter()
-        .filter_map(|result| {
-            let instance = result.metric.get("instance")?.clone();
-            let temp: f64 = result.value.1.parse().ok()?;
-            Some((instance, temp))
-        })
+        .flatten()
+        .chain(custom_maps.iter().map(|(_, map)| map))
+        .flat_map(HashMap::keys)
         .collect();
+    let reading = |map: &Option<HashMap<SensorKey, f64>>, sensor: &SensorKey| match map {
+        Some(map) => map.get(sensor).copied().ok_or(MissingReason::NoData),
+        None => Err(MissingReason::QueryFailed),
+    };
 
-    // Aggregate temperatures by instance (group multiple sensors per blade)
-    let mut instance_groups: HashMap<String, Vec<(f64, f64, f64)>> = HashMap::new();
+    for sensor in sensors {
+        let minutely_temp = reading(&minutely_map, sensor);
+        let hourly_temp = reading(&hourly_map, sensor);
+        let daily_temp = reading(&daily_map, sensor);
 
-    for instance in minutely_map.keys() {
-        let minutely_temp = minutely_map.get(instance).copied().unwrap_or(0.0);
-        let hourly_temp = hourly_map.get(instance).copied().unwrap_or(0.0);
-        let daily_temp = daily_map.get(instance).copied().unwrap_or(0.0);
+        // Requested ?windows= values, only when asked for
+        let windows = (!custom_maps.is_empty()).then(|| {
+            let mut windows = WindowValues::new();
+            for (window_stat, map) in &custom_maps {
+                if let Some(value) = map.get(sensor) {
+                    windows
+                        .entry(window_stat.window.clone())
+                        .or_default()
+                        .insert(window_stat.stat.to_string(), round_to_tenth(*value));
+                }
+            }
+            windows
+        });
 
-        instance_groups
-            .entry(instance.clone())
+        node_groups
+            .entry(node_map.node_for(&sensor.instance))
             .or_default()
-            .push((minutely_temp, hourly_temp, daily_temp));
+            .push(SensorReading {
+                sensor: sensor.clone(),
+                minutely: minutely_temp,
+                hourly: hourly_temp,
+                daily: daily_temp,
+                windows,
+            });
     }
 
-    // Create blade names using the IP to node mapping and maximum temperatures
-    for (instance, temps) in instance_groups {
-        let blade_name = instance_to_blade_name(&instance, ip_to_node_map);
-        
+    // Maximum temperatures per node
+    for (blade_name, temps) in node_groups {
         if !temps.is_empty() {
-            let (max_min, max_hour, max_day) = temps.iter().fold((f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY), |acc, &(m, h, d)| {
-                (acc.0.max(m), acc.1.max(h), acc.2.max(d))
+            let max_min = max_reading(temps.iter().map(|reading| reading.minutely));
+            let max_hour = max_reading(temps.iter().map(|reading| reading.hourly));
+            let max_day = max_reading(temps.iter().map(|reading| reading.daily));
+            let missing = missing_windows([("minutely", max_min), ("hourly", max_hour), ("daily", max_day)]);
+
+            // Every statistic of a node comes from its hottest sensor for that statistic
+            let windows = (!custom_maps.is_empty()).then(|| {
+                let mut windows = WindowValues::new();
+                for reading in &temps {
+                    if let Some(sensor_windows) = &reading.windows {
+                        merge_max(&mut windows, sensor_windows);
+                    }
+                }
+                windows
+            });
+
+            // Judge every sensor by its own limits on the current (minutely) reading
+            let no_labels = SensorLabels::default();
+            let labels = sensor_labels.unwrap_or(&no_labels);
+            let evaluated: Vec<(Limits, Status)> = temps
+                .iter()
+                .map(|reading| {
+                    let label = labels.label(&reading.sensor);
+                    let limits = thresholds.limits_for(&blade_name, &reading.sensor, label.as_deref());
+                    (limits, reading.minutely.map_or(Status::Unknown, |value| limits.status(value)))
+                })
+                .collect();
+            let status = worst_judged(evaluated.iter().map(|(_, status)| *status));
+
+            // Per-sensor breakdown, only when requested with ?detail=sensors
+            let sensors = include_sensors.then(|| {
+                let mut sensors: Vec<SensorMeasurement> = temps
+                    .iter()
+                    .zip(&evaluated)
+                    .map(|(reading, (limits, status))| SensorMeasurement {
+                        instance: reading.sensor.instance.clone(),
+                        chip: reading.sensor.chip.clone(),
+                        chip_name: labels.chip_name(&reading.sensor),
+                        sensor: reading.sensor.sensor.clone(),
+                        label: labels.label(&reading.sensor),
+                        minutely_temperature: reading.minutely.ok().map(round_to_tenth),
+                        hourly_temperature: reading.hourly.ok().map(f64::round),
+                        daily_temperature: reading.daily.ok().map(round_to_tenth),
+                        missing: missing_windows([
+                            ("minutely", reading.minutely),
+                            ("hourly", reading.hourly),
+                            ("daily", reading.daily),
+                        ]),
+                        windows: reading.windows.clone(),
+                        status: *status,
+                        thresholds: *limits,
+                        headroom: reading.minutely.ok().and_then(|value| Headroom::to_critical(value, limits)),
+                    })
+                    .collect();
+                sensors.sort_by(|a, b| (&a.chip, &a.sensor, &a.instance).cmp(&(&b.chip, &b.sensor, &b.instance)));
+                sensors
             });
 
             blade_temperatures.insert(
                 blade_name.clone(),
                 TemperatureMeasurement {
                     node: blade_name,
-                    minutely_temperature: (max_min * 10.0).round() / 10.0, // Round to 1 decimal
-                    hourly_temperature: max_hour.round(),                   // Round to integer
-                    daily_temperature: (max_day * 10.0).round() / 10.0,    // Round to 1 decimal
+                    cluster: None,
+                    minutely_temperature: max_min.ok().map(round_to_tenth), // Round to 1 decimal
+                    hourly_temperature: max_hour.ok().map(f64::round),      // Round to integer
+                    daily_temperature: max_day.ok().map(round_to_tenth),    // Round to 1 decimal
+                    missing,
+                    // Filled in from the presence data
+                    last_seen: None,
+                    status,
+                    windows,
+                    sensors,
                 },
             );
         }
     }
 }
 
-fn instance_to_blade_name(instance: &str, ip_to_node_map: &HashMap<String, String>) -> String {
-    info!("Looking up instance: {} in mapping", instance);
-    // Try to get the blade name from the IP to node mapping using the full instance (IP:port)
-    if let Some(node_name) = ip_to_node_map.get(instance) {
-        info!("Found mapping: {} -> {}", instance, node_name);
-        node_name.clone()
-    } else {
-        warn!("No mapping found for instance: {}, available keys: {:?}", instance, ip_to_node_map.keys().collect::<Vec<_>>());
-        "unknown_blade".to_string()        
+// Readings of one sensor across all queried windows
+struct SensorReading {
+    sensor: SensorKey,
+    minutely: Reading,
+    hourly: Reading,
+    daily: Reading,
+    windows: Option<WindowValues>,
+}
+
+// Readings keyed by sensor; duplicates of one sensor (e.g. scraped by two jobs) keep the maximum
+fn sensor_map(samples: Vec<InstantSample>) -> HashMap<SensorKey, f64> {
+    let mut map: HashMap<SensorKey, f64> = HashMap::new();
+    for sample in samples {
+        if let Some(key) = SensorKey::from_sample(&sample) {
+            let value = map.entry(key).or_insert(f64::NEG_INFINITY);
+            *value = value.max(sample.value);
+        }
     }
+    map
+}
+
+fn round_to_tenth(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
 }
 
 async fn health_check() -> &'static str {
@@ -274,26 +603,602 @@ async fn main() {
     // Initialize tracing
     tracing_subscriber::fmt::init();
 
+    let config = Config::load().expect("Failed to load configuration");
+    let listen_addr = config.listen_addr().expect("Invalid listen address");
+    let state = AppState::new(config.clone()).expect("Failed to initialize application state");
+
+    if config.alerting.enabled {
+        let alerting_state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = alerting::run(alerting_state).await {
+                error!("Alerting stopped: {}", e);
+            }
+        });
+    }
+
+    // Keep the instance to node mappings current so requests don't have to load them: the default datasource and
+    // the federated clusters, plus every other datasource when requests may select them
+    let refreshed: BTreeSet<&String> = if config.datasources.allow_selection {
+        state.datasources.keys().collect()
+    } else {
+        std::iter::once(&config.datasources.default).chain(&config.federation.clusters).collect()
+    };
+    for name in refreshed {
+        tokio::spawn(nodes::run(state.datasources[name].nodes.clone()));
+    }
+
+    // Single poller behind /api/temperatures/stream, idle while nobody is subscribed
+    tokio::spawn(live::run(state.clone()));
+
     // Build application router
     let app = Router::new()
         .route("/", get(health_check))
         .route("/health", get(health_check))
         .route("/api/temperatures", get(get_temperatures))
-        .layer(CorsLayer::permissive());
+        .route("/api/temperatures/history", get(history::get_temperature_history))
+        .route("/api/temperatures/stream", get(live::stream_temperatures))
+        .route("/api/temperatures/ws", get(websocket::temperatures_socket))
+        .route("/api/nodes/mapping", get(nodes::get_node_mapping))
+        .route("/api/nodes/:node", get(node_detail::get_node))
+        .layer(CorsLayer::permissive())
+        .with_state(state);
 
     // Start server
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
+    let listener = tokio::net::TcpListener::bind(listen_addr)
         .await
-        .expect("Failed to bind to port 3000");
+        .unwrap_or_else(|e| panic!("Failed to bind to {}: {}", listen_addr, e));
 
-    info!("Temperature Monitor API Server starting on http://0.0.0.0:3000");
+    info!("Temperature Monitor API Server starting on http://{}", listen_addr);
+    for (name, url) in config.datasources() {
+        let default = if name == config.datasources.default { " (default)" } else { "" };
+        info!("Datasource {}{}: {}", name, default, url);
+    }
     info!("Endpoints:");
     info!("  GET /                 - Health check");
     info!("  GET /health           - Health check");
     info!("  GET /api/temperatures - Get blade server temperatures");
-    info!("  GET /api/temperatures?dev=true - Use localhost:8429 for development");
+    info!("  GET /api/temperatures/history?node=&start=&end=&step= - Get temperature time series");
+    info!("  GET /api/temperatures/stream - Server-Sent Events with live snapshots and status changes");
+    info!("  GET /api/temperatures/ws - WebSocket with filtered deltas, snapshot and history commands");
+    info!("  GET /api/nodes/mapping - Instance to node mapping, including unmapped instances");
+    info!("  GET /api/nodes/{{node}} - Readings, thresholds, instances and a sparkline for one node");
+    if config.datasources.allow_selection {
+        info!("  GET /api/temperatures?datasource=<name> - Query another configured datasource");
+    }
+    if !config.federation.clusters.is_empty() {
+        info!(
+            "Federating {} cluster(s): {}, {} deadline per cluster",
+            config.federation.clusters.len(),
+            config.federation.clusters.join(", "),
+            config.federation.cluster_deadline
+        );
+    }
 
     axum::serve(listener, app)
         .await
         .expect("Failed to start server");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::memory::{sample, InMemoryBackend};
+    use chrono::Duration;
+    use config::{GroupThresholds, SensorThresholds};
+    use test_support::{pod, state_with, state_with_config, NODE_EXPORTER_PODS};
+
+    fn fleet_backend() -> InMemoryBackend {
+        InMemoryBackend::new()
+            .with_series(
+                NODE_EXPORTER_PODS,
+                vec![
+                    pod("node-exporter-a", "10.0.0.1", "blade001"),
+                    pod("node-exporter-b", "10.0.0.2", "blade002"),
+                ],
+            )
+            .with_instant(
+                "max_over_time(node_hwmon_temp_celsius[1m])",
+                vec![
+                    sample(&[("instance", "10.0.0.1:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp2")], 73.34),
+                    sample(&[("instance", "10.0.0.1:9100"), ("chip", "nvme_nvme0"), ("sensor", "temp1")], 61.26),
+                    sample(&[("instance", "10.0.0.2:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp1")], 55.0),
+                ],
+            )
+            .with_instant(
+                "max_over_time(node_hwmon_temp_celsius[1h])",
+                vec![
+                    sample(&[("instance", "10.0.0.1:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp2")], 74.4),
+                    sample(&[("instance", "10.0.0.1:9100"), ("chip", "nvme_nvme0"), ("sensor", "temp1")], 62.0),
+                    sample(&[("instance", "10.0.0.2:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp1")], 58.6),
+                ],
+            )
+            .with_instant(
+                "max_over_time(node_hwmon_temp_celsius[1d])",
+                vec![
+                    sample(&[("instance", "10.0.0.1:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp2")], 83.21),
+                    sample(&[("instance", "10.0.0.1:9100"), ("chip", "nvme_nvme0"), ("sensor", "temp1")], 64.0),
+                    sample(&[("instance", "10.0.0.2:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp1")], 60.0),
+                ],
+            )
+    }
+
+    async fn temperatures(backend: InMemoryBackend, params: QueryParams) -> TemperatureResponse {
+        fetch_temperatures(&state_with(backend), &params).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn instances_of_one_node_are_merged() {
+        // The exporter of blade001 is scraped under its host and its pod IP
+        let host = [("instance", "10.0.0.1:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp1")];
+        let pod_ip = [("instance", "10.244.0.7:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp1")];
+        let backend = InMemoryBackend::new()
+            .with_series(
+                NODE_EXPORTER_PODS,
+                vec![pod("node-exporter-a", "10.0.0.1", "blade001"), pod("node-exporter-a", "10.244.0.7", "blade001")],
+            )
+            .with_instant("max_over_time(node_hwmon_temp_celsius[1m])", vec![sample(&host, 80.0)])
+            .with_instant("max_over_time(node_hwmon_temp_celsius[1h])", vec![sample(&host, 45.0), sample(&pod_ip, 50.0)])
+            .with_instant("max_over_time(node_hwmon_temp_celsius[1d])", vec![sample(&pod_ip, 90.0)]);
+        let params = QueryParams {
+            detail: Some(Detail::Sensors),
+            ..QueryParams::default()
+        };
+
+        let response = temperatures(backend, params).await;
+
+        assert_eq!(response.measurements.len(), 1);
+        let blade = &response.measurements[0];
+        assert_eq!(blade.node, "blade001");
+        assert_eq!(blade.minutely_temperature, Some(80.0));
+        assert_eq!(blade.hourly_temperature, Some(50.0));
+        assert_eq!(blade.daily_temperature, Some(90.0));
+        assert!(blade.missing.is_empty());
+        // The same sensor under both instances, told apart by the instance
+        let sensors = blade.sensors.as_ref().unwrap();
+        let instances: Vec<&str> = sensors.iter().map(|sensor| sensor.instance.as_str()).collect();
+        assert_eq!(instances, ["10.0.0.1:9100", "10.244.0.7:9100"]);
+        assert_eq!(sensors[0].minutely_temperature, Some(80.0));
+        assert_eq!(sensors[1].minutely_temperature, None);
+    }
+
+    #[tokio::test]
+    async fn temperatures_take_hottest_sensor_per_node() {
+        let response = temperatures(fleet_backend(), QueryParams::default()).await;
+
+        assert_eq!(response.measurements.len(), 2);
+        let blade = &response.measurements[0];
+        assert_eq!(blade.node, "blade001");
+        assert_eq!(blade.minutely_temperature, Some(73.3));
+        assert_eq!(blade.hourly_temperature, Some(74.0));
+        assert_eq!(blade.daily_temperature, Some(83.2));
+        assert!(blade.sensors.is_none());
+        assert_eq!(response.measurements[1].node, "blade002");
+    }
+
+    #[tokio::test]
+    async fn temperatures_without_data_are_empty() {
+        let response = temperatures(InMemoryBackend::new(), QueryParams::default()).await;
+
+        assert!(response.measurements.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sensor_detail_lists_every_sensor_with_labels() {
+        let backend = fleet_backend()
+            .with_instant(
+                "node_hwmon_sensor_label",
+                vec![sample(
+                    &[("instance", "10.0.0.1:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp2"), ("label", "Package id 0")],
+                    1.0,
+                )],
+            )
+            .with_instant(
+                "node_hwmon_chip_names",
+                vec![sample(&[("instance", "10.0.0.1:9100"), ("chip", "nvme_nvme0"), ("chip_name", "nvme")], 1.0)],
+            );
+        let params = QueryParams {
+            detail: Some(Detail::Sensors),
+            ..QueryParams::default()
+        };
+
+        let response = temperatures(backend, params).await;
+
+        let sensors = response.measurements[0].sensors.as_ref().unwrap();
+        assert_eq!(sensors.len(), 2);
+        assert_eq!(sensors[0].chip, "nvme_nvme0");
+        assert_eq!(sensors[0].chip_name.as_deref(), Some("nvme"));
+        assert_eq!(sensors[0].label, None);
+        assert_eq!(sensors[0].daily_temperature, Some(64.0));
+        assert_eq!(sensors[1].sensor, "temp2");
+        assert_eq!(sensors[1].label.as_deref(), Some("Package id 0"));
+        assert_eq!(sensors[1].minutely_temperature, Some(73.3));
+    }
+
+    #[tokio::test]
+    async fn custom_windows_report_each_statistic() {
+        let backend = fleet_backend()
+            .with_instant(
+                "avg_over_time(node_hwmon_temp_celsius[5m])",
+                vec![
+                    sample(&[("instance", "10.0.0.1:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp2")], 70.04),
+                    sample(&[("instance", "10.0.0.1:9100"), ("chip", "nvme_nvme0"), ("sensor", "temp1")], 60.0),
+                ],
+            )
+            .with_instant(
+                "quantile_over_time(0.95, node_hwmon_temp_celsius[5m])",
+                vec![sample(&[("instance", "10.0.0.1:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp2")], 72.5)],
+            );
+        let params = QueryParams {
+            windows: Some("5m".to_string()),
+            stat: Some("avg,p95".to_string()),
+            ..QueryParams::default()
+        };
+
+        let response = temperatures(backend, params).await;
+
+        let windows = response.measurements[0].windows.as_ref().unwrap();
+        assert_eq!(windows["5m"]["avg"], 70.0);
+        assert_eq!(windows["5m"]["p95"], 72.5);
+        // Legacy fields stay in place for existing clients
+        assert_eq!(response.measurements[0].minutely_temperature, Some(73.3));
+    }
+
+    #[tokio::test]
+    async fn status_is_unknown_without_thresholds() {
+        let response = temperatures(fleet_backend(), QueryParams::default()).await;
+
+        assert_eq!(response.status, Status::Unknown);
+        assert!(response.measurements.iter().all(|m| m.status == Status::Unknown));
+    }
+
+    #[tokio::test]
+    async fn most_specific_threshold_decides_status() {
+        let mut config = Config::default();
+        config.thresholds.warning = Some(70.0);
+        config.thresholds.critical = Some(90.0);
+        config.thresholds.groups.push(GroupThresholds {
+            name: "quiet".to_string(),
+            nodes: vec!["blade00?".to_string()],
+            warning: Some(80.0),
+            critical: None,
+        });
+        config.thresholds.sensors.push(SensorThresholds {
+            nodes: None,
+            chip: Some("nvme_*".to_string()),
+            sensor: None,
+            label: None,
+            warning: Some(60.0),
+            critical: Some(61.0),
+        });
+        let params = QueryParams {
+            detail: Some(Detail::Sensors),
+            ..QueryParams::default()
+        };
+
+        let response = fetch_temperatures(&state_with_config(fleet_backend(), config), &params)
+            .await
+            .unwrap();
+
+        // blade001: the NVMe rule makes 61.3 critical, the group keeps 73.3 on the CPU below warning
+        let blade = &response.measurements[0];
+        assert_eq!(blade.status, Status::Critical);
+        let sensors = blade.sensors.as_ref().unwrap();
+        assert_eq!(sensors[0].status, Status::Critical);
+        assert_eq!(sensors[1].status, Status::Ok);
+        assert_eq!(sensors[1].thresholds, Limits::new(Some(80.0), Some(90.0)));
+        assert_eq!(response.measurements[1].status, Status::Ok);
+        assert_eq!(response.status, Status::Critical);
+    }
+
+    #[tokio::test]
+    async fn hwmon_limits_apply_without_configured_thresholds() {
+        let coretemp = [("instance", "10.0.0.1:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp2")];
+        let backend = fleet_backend()
+            .with_instant("node_hwmon_temp_max_celsius", vec![sample(&coretemp, 70.0)])
+            .with_instant("node_hwmon_temp_crit_celsius", vec![sample(&coretemp, 100.0)]);
+        let params = QueryParams {
+            detail: Some(Detail::Sensors),
+            ..QueryParams::default()
+        };
+
+        let response = temperatures(backend, params).await;
+
+        let blade = &response.measurements[0];
+        assert_eq!(blade.status, Status::Warning);
+        let sensors = blade.sensors.as_ref().unwrap();
+        // The NVMe drive exposes no limits of its own
+        assert_eq!(sensors[0].status, Status::Unknown);
+        assert_eq!(sensors[0].headroom, None);
+        assert_eq!(sensors[1].thresholds, Limits::new(Some(70.0), Some(100.0)));
+        assert_eq!(sensors[1].headroom, Some(Headroom { degrees: 26.7, percent: 26.7 }));
+    }
+
+    #[tokio::test]
+    async fn sensors_without_limits_do_not_hide_the_node_status() {
+        let coretemp = [("instance", "10.0.0.1:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp2")];
+        let backend = fleet_backend()
+            .with_instant("node_hwmon_temp_max_celsius", vec![sample(&coretemp, 80.0)])
+            .with_instant("node_hwmon_temp_crit_celsius", vec![sample(&coretemp, 100.0)]);
+
+        let response = temperatures(backend, QueryParams::default()).await;
+
+        // The NVMe drive cannot be judged, the CPU is fine
+        assert_eq!(response.measurements[0].status, Status::Ok);
+        // Nodes without any limits stay unknown, but do not hide the fleet status either
+        assert_eq!(response.measurements[1].status, Status::Unknown);
+        assert_eq!(response.status, Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn blades_missing_from_short_windows_are_null_not_zero() {
+        let backend = fleet_backend()
+            .with_series(
+                NODE_EXPORTER_PODS,
+                vec![
+                    pod("node-exporter-a", "10.0.0.1", "blade001"),
+                    pod("node-exporter-b", "10.0.0.2", "blade002"),
+                    pod("node-exporter-c", "10.0.0.3", "blade003"),
+                ],
+            )
+            .with_instant(
+                "max_over_time(node_hwmon_temp_celsius[1d])",
+                vec![
+                    sample(&[("instance", "10.0.0.1:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp2")], 83.21),
+                    sample(&[("instance", "10.0.0.3:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp1")], 66.0),
+                ],
+            );
+
+        let response = temperatures(backend, QueryParams::default()).await;
+
+        let offline = response.measurements.iter().find(|m| m.node == "blade003").unwrap();
+        assert_eq!(offline.minutely_temperature, None);
+        assert_eq!(offline.hourly_temperature, None);
+        assert_eq!(offline.daily_temperature, Some(66.0));
+        assert_eq!(offline.missing["minutely"], MissingReason::NoData);
+        // Without a last seen sample it has been silent for longer than the lookback
+        assert_eq!(offline.status, Status::Offline);
+        // blade002 has no daily sample anymore
+        let blade = response.measurements.iter().find(|m| m.node == "blade002").unwrap();
+        assert_eq!(blade.daily_temperature, None);
+        assert_eq!(blade.missing, MissingWindows::from([("daily".to_string(), MissingReason::NoData)]));
+    }
+
+    #[tokio::test]
+    async fn silent_and_unreporting_nodes_are_offline() {
+        let now = Utc::now().timestamp() as f64;
+        let backend = fleet_backend()
+            .with_instant(
+                "max_over_time(node_hwmon_temp_celsius[1m])",
+                vec![sample(&[("instance", "10.0.0.1:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp2")], 73.34)],
+            )
+            .with_instant(
+                "max by (instance) (max_over_time(timestamp(node_hwmon_temp_celsius)[1h:1m]))",
+                vec![sample(&[("instance", "10.0.0.1:9100")], now - 15.0), sample(&[("instance", "10.0.0.2:9100")], now - 600.0)],
+            )
+            .with_instant(
+                "kube_node_info",
+                ["blade001", "blade002", "blade003"].map(|node| sample(&[("node", node)], 1.0)).to_vec(),
+            );
+
+        let response = temperatures(backend, QueryParams::default()).await;
+
+        let nodes: Vec<(&str, Status)> = response.measurements.iter().map(|m| (m.node.as_str(), m.status)).collect();
+        assert_eq!(nodes, [("blade001", Status::Unknown), ("blade002", Status::Offline), ("blade003", Status::Offline)]);
+        let silent = &response.measurements[1];
+        assert_eq!(silent.last_seen.map(|seen| seen.timestamp()), Some(now as i64 - 600));
+        assert_eq!(silent.hourly_temperature, Some(59.0));
+        assert_eq!(response.measurements[2].last_seen, None);
+        assert_eq!(response.status, Status::Offline);
+    }
+
+    #[tokio::test]
+    async fn node_and_label_filters_are_pushed_into_the_selector() {
+        let selector = r#"node_hwmon_temp_celsius{job="node-exporter",instance=~"10\\.0\\.0\\.2:9100"}"#;
+        let mut backend = fleet_backend()
+            .with_instant(
+                "count by (instance) (node_hwmon_temp_celsius)",
+                vec![sample(&[("instance", "10.0.0.1:9100")], 2.0), sample(&[("instance", "10.0.0.2:9100")], 1.0)],
+            )
+            .with_instant(
+                "kube_node_info",
+                ["blade001", "blade002", "blade003"].map(|node| sample(&[("node", node)], 1.0)).to_vec(),
+            );
+        for window in ["1m", "1h", "1d"] {
+            backend = backend.with_instant(
+                format!("max_over_time({}[{}])", selector, window),
+                vec![sample(&[("instance", "10.0.0.2:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp1")], 55.0)],
+            );
+        }
+        let params = QueryParams {
+            node: Some("blade00*".to_string()),
+            node_regex: Some(".*2".to_string()),
+            labels: Some("job=node-exporter".to_string()),
+            ..QueryParams::default()
+        };
+
+        let response = temperatures(backend, params).await;
+
+        let nodes: Vec<&str> = response.measurements.iter().map(|m| m.node.as_str()).collect();
+        assert_eq!(nodes, ["blade002"]);
+        assert_eq!(response.measurements[0].minutely_temperature, Some(55.0));
+    }
+
+    #[tokio::test]
+    async fn node_filters_keep_silent_nodes() {
+        // blade003 stopped reporting a while ago, so only the daily lookback still lists its instance
+        let selector = r#"node_hwmon_temp_celsius{job="node-exporter",instance=~"10\\.0\\.0\\.3:9100"}"#;
+        let coretemp = [("instance", "10.0.0.3:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp1")];
+        let backend = InMemoryBackend::new()
+            .with_series(NODE_EXPORTER_PODS, vec![pod("node-exporter-c", "10.0.0.3", "blade003")])
+            .with_instant(
+                "count by (instance) (last_over_time(node_hwmon_temp_celsius[1d]))",
+                vec![sample(&[("instance", "10.0.0.3:9100")], 1.0)],
+            )
+            .with_instant(format!("max_over_time({}[1h])", selector), vec![sample(&coretemp, 64.0)])
+            .with_instant(format!("max_over_time({}[1d])", selector), vec![sample(&coretemp, 81.0)]);
+        let params = QueryParams {
+            node: Some("blade003".to_string()),
+            labels: Some("job=node-exporter".to_string()),
+            ..QueryParams::default()
+        };
+
+        let response = temperatures(backend, params).await;
+
+        assert_eq!(response.measurements.len(), 1);
+        let blade = &response.measurements[0];
+        assert_eq!(blade.node, "blade003");
+        assert_eq!(blade.minutely_temperature, None);
+        assert_eq!(blade.hourly_temperature, Some(64.0));
+        assert_eq!(blade.daily_temperature, Some(81.0));
+    }
+
+    #[tokio::test]
+    async fn invalid_label_matchers_are_rejected() {
+        let params = QueryParams {
+            labels: Some("job~node".to_string()),
+            ..QueryParams::default()
+        };
+
+        let error = fetch_temperatures(&state_with(fleet_backend()), &params).await.unwrap_err();
+
+        assert!(matches!(error, ApiError::BadRequest(_)));
+    }
+
+    #[tokio::test]
+    async fn other_datasources_need_to_be_allowed() {
+        let mut config = Config::default();
+        config.datasources.sources.insert("staging".to_string(), "http://staging:8429".to_string());
+        let params = |datasource: &str| QueryParams {
+            datasource: Some(datasource.to_string()),
+            ..QueryParams::default()
+        };
+
+        let state = state_with_config(fleet_backend(), config.clone());
+        let error = fetch_temperatures(&state, &params("staging")).await.unwrap_err();
+        assert!(matches!(error, ApiError::Forbidden(_)));
+        // Naming the default datasource is always fine
+        assert!(fetch_temperatures(&state, &params("default")).await.is_ok());
+
+        config.datasources.allow_selection = true;
+        let state = state_with_config(fleet_backend(), config);
+        assert_eq!(fetch_temperatures(&state, &params("staging")).await.unwrap().measurements.len(), 2);
+        let error = fetch_temperatures(&state, &params("local")).await.unwrap_err();
+        assert!(matches!(error, ApiError::BadRequest(_)));
+    }
+
+    #[tokio::test]
+    async fn federation_tags_clusters_and_reports_failures() {
+        let mut config = Config::default();
+        for cluster in ["east", "west"] {
+            config.datasources.sources.insert(cluster.to_string(), format!("http://{}:8429", cluster));
+            config.federation.clusters.push(cluster.to_string());
+        }
+        let state = state_with_config(fleet_backend(), config);
+
+        let response = fetch_temperatures(&state, &QueryParams::default()).await.unwrap();
+        let keys: Vec<String> = response.measurements.iter().map(TemperatureMeasurement::key).collect();
+        assert_eq!(keys, ["east/blade001", "east/blade002", "west/blade001", "west/blade002"]);
+        assert!(response.cluster_errors.is_empty());
+
+        // One unreachable cluster leaves the others' data in place
+        let west = fetch_datasource(&state, state.datasource(Some("west")).unwrap(), &QueryParams::default()).await;
+        let unreachable = ApiError::UpstreamUnreachable("connection refused".to_string());
+        let merged = merge_clusters(vec![("east", Err(unreachable)), ("west", west)]).unwrap();
+        assert_eq!(merged.measurements.len(), 2);
+        assert_eq!(merged.measurements[0].cluster.as_deref(), Some("west"));
+        assert_eq!(merged.cluster_errors.len(), 1);
+        assert_eq!(merged.cluster_errors[0].cluster, "east");
+        assert_eq!(merged.cluster_errors[0].status, 502);
+
+        let timeout = ApiError::UpstreamTimeout("no answer".to_string());
+        assert!(matches!(merge_clusters(vec![("east", Err(timeout))]), Err(ApiError::UpstreamTimeout(_))));
+    }
+
+    #[tokio::test]
+    async fn failed_window_is_reported_and_others_kept() {
+        let backend = fleet_backend()
+            .with_error("max_over_time(node_hwmon_temp_celsius[1d])", "timeout", "query timed out in expression evaluation")
+            .with_warning("max_over_time(node_hwmon_temp_celsius[1h])", "results truncated due to limit");
+
+        let response = temperatures(backend, QueryParams::default()).await;
+
+        let blade = &response.measurements[0];
+        assert_eq!(blade.minutely_temperature, Some(73.3));
+        assert_eq!(blade.hourly_temperature, Some(74.0));
+        assert_eq!(blade.daily_temperature, None);
+        assert_eq!(blade.missing, MissingWindows::from([("daily".to_string(), MissingReason::QueryFailed)]));
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(response.errors[0].window, "1d");
+        assert_eq!(response.errors[0].error_type, "timeout");
+        assert_eq!(response.warnings, vec!["results truncated due to limit".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn invalid_statistic_is_rejected() {
+        let params = QueryParams {
+            windows: Some("5m".to_string()),
+            stat: Some("median".to_string()),
+            ..QueryParams::default()
+        };
+
+        let result = get_temperatures(State(state_with(fleet_backend())), Ok(Query(params))).await;
+
+        assert!(matches!(result.unwrap_err(), ApiError::BadRequest(_)));
+    }
+
+    #[tokio::test]
+    async fn statistics_need_windows() {
+        let params = QueryParams {
+            stat: Some("avg".to_string()),
+            ..QueryParams::default()
+        };
+
+        let error = fetch_temperatures(&state_with(fleet_backend()), &params).await.unwrap_err();
+
+        assert!(matches!(error, ApiError::BadRequest(_)));
+    }
+
+    #[tokio::test]
+    async fn repeated_windows_count_once() {
+        let params = QueryParams {
+            windows: Some("1m,2m,3m,4m,5m,6m,7m,8m,5m,1m".to_string()),
+            ..QueryParams::default()
+        };
+
+        let response = fetch_temperatures(&state_with(fleet_backend()), &params).await.unwrap();
+
+        assert_eq!(response.measurements.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn last_good_response_is_served_while_upstream_is_down() {
+        let good = temperatures(fleet_backend(), QueryParams::default()).await;
+        let state = state_with(InMemoryBackend::unavailable());
+        state.last_good.record(&QueryParams::default(), &good, Utc::now() - Duration::seconds(42));
+
+        let response = get_temperatures(State(state), Ok(Query(QueryParams::default()))).await.unwrap();
+
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let warning = response.headers()[header::WARNING].to_str().unwrap();
+        assert!(warning.starts_with("110 - "), "{}", warning);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["stale"], true);
+        assert_eq!(body["data_age_seconds"], 42);
+        assert_eq!(body["measurements"][0]["node"], "blade001");
+    }
+
+    #[tokio::test]
+    async fn too_old_or_missing_data_is_not_served() {
+        let good = temperatures(fleet_backend(), QueryParams::default()).await;
+        let state = state_with(InMemoryBackend::unavailable());
+
+        let missing = get_temperatures(State(state.clone()), Ok(Query(QueryParams::default()))).await;
+        assert!(matches!(missing.unwrap_err(), ApiError::UpstreamUnreachable(_)));
+
+        // Default maximum staleness is 10 minutes
+        state.last_good.record(&QueryParams::default(), &good, Utc::now() - Duration::minutes(11));
+        let expired = get_temperatures(State(state), Ok(Query(QueryParams::default()))).await;
+        assert!(matches!(expired.unwrap_err(), ApiError::StaleDataExpired(_)));
+    }
+}
@@ -0,0 +1,198 @@
+use crate::config::WindowsConfig;
+use crate::error::ApiError;
+use crate::history::{node_series, HistoryPoint};
+use crate::nodes::InstanceMapping;
+use crate::state::AppState;
+use crate::windows::WindowError;
+use crate::{fetch_temperatures, Detail, QueryParams, TemperatureMeasurement};
+use axum::{
+    extract::{rejection::QueryRejection, Path, Query, State},
+    response::Json,
+};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+// Time span and resolution of the sparkline
+const SPARKLINE_RANGE_MINUTES: i64 = 60;
+const SPARKLINE_STEP_SECONDS: i64 = 60;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct NodeParams {
+    datasource: Option<String>,
+}
+
+/// Response of `GET /api/nodes/{node}`.
+#[derive(Debug, Serialize)]
+pub struct NodeDetail {
+    // Current readings, status and last_seen, always with the per-sensor breakdown
+    #[serde(flatten)]
+    measurement: TemperatureMeasurement,
+    // Durations behind minutely, hourly and daily
+    configured_windows: WindowsConfig,
+    // Scraped instances that resolve to this node
+    instances: Vec<InstanceMapping>,
+    // Hottest reading per step over the last hour; null when the range query failed
+    sparkline: Option<Sparkline>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<WindowError>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Sparkline {
+    step_seconds: i64,
+    points: Vec<HistoryPoint>,
+}
+
+pub async fn get_node(
+    State(state): State<AppState>,
+    Path(node): Path<String>,
+    params: Result<Query<NodeParams>, QueryRejection>,
+) -> Result<Json<NodeDetail>, ApiError> {
+    let Query(params) = params?;
+    tokio::time::timeout(state.request_deadline, fetch_node(&state, &node, &params))
+        .await
+        .map_err(|_| {
+            warn!("Node request exceeded deadline of {:?}", state.request_deadline);
+            ApiError::UpstreamTimeout(format!("no answer within the request deadline of {:?}", state.request_deadline))
+        })?
+        .map(Json)
+}
+
+pub async fn fetch_node(state: &AppState, node: &str, params: &NodeParams) -> Result<NodeDetail, ApiError> {
+    // Only this node's instances are queried, instead of the whole fleet
+    let query_params = QueryParams {
+        datasource: params.datasource.clone(),
+        detail: Some(Detail::Sensors),
+        node_regex: Some(regex::escape(node)),
+        ..QueryParams::default()
+    };
+    let response = fetch_temperatures(state, &query_params).await?;
+    let mut found: Vec<TemperatureMeasurement> =
+        response.measurements.into_iter().filter(|measurement| measurement.node == node).collect();
+    // Federated clusters may each have a node of that name; picking one silently could show the wrong machine
+    if found.len() > 1 {
+        let clusters: Vec<&str> = found.iter().filter_map(|measurement| measurement.cluster.as_deref()).collect();
+        return Err(ApiError::Conflict(format!(
+            "node {} exists in clusters {}, choose one with ?datasource=",
+            node,
+            clusters.join(", ")
+        )));
+    }
+    let measurement = found.pop().ok_or_else(|| ApiError::NotFound(format!("unknown node {}", node)))?;
+
+    // When federated, the history and instances come from the cluster that reported the node
+    let datasource_name = measurement.cluster.as_deref().or(params.datasource.as_deref());
+    let datasource = state.datasource(datasource_name)?;
+    let end = Utc::now();
+    let start = end - Duration::minutes(SPARKLINE_RANGE_MINUTES);
+    let (sparkline, instances) = tokio::join!(
+        node_series(state, datasource_name, Some(node), start, end, SPARKLINE_STEP_SECONDS),
+        datasource.nodes.instances_of(node)
+    );
+
+    // The sparkline is decoration; a failed range query should not hide the current readings
+    let sparkline = sparkline.ok().map(|series| Sparkline {
+        step_seconds: SPARKLINE_STEP_SECONDS,
+        points: series.into_iter().next().map(|series| series.points).unwrap_or_default(),
+    });
+
+    Ok(NodeDetail {
+        measurement,
+        configured_windows: state.config.query.windows.clone(),
+        instances,
+        sparkline,
+        errors: response.errors,
+        warnings: response.warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory::{sample, InMemoryBackend};
+    use crate::backend::RangeSeries;
+    use crate::nodes::Source;
+    use crate::config::Config;
+    use crate::test_support::{pod, state_with, state_with_config, NODE_EXPORTER_PODS};
+    use std::collections::HashMap;
+
+    fn backend() -> InMemoryBackend {
+        let readings = vec![sample(
+            &[("instance", "10.0.0.1:9100"), ("chip", "platform_coretemp_0"), ("sensor", "temp2")],
+            73.34,
+        )];
+        let mut backend = InMemoryBackend::new()
+            .with_series(
+                NODE_EXPORTER_PODS,
+                vec![
+                    pod("node-exporter-a", "10.0.0.1", "blade001"),
+                    pod("node-exporter-b", "10.0.0.2", "blade002"),
+                ],
+            )
+            .with_instant(
+                "count by (instance) (node_hwmon_temp_celsius)",
+                vec![
+                    sample(&[("instance", "10.0.0.1:9100")], 1.0),
+                    sample(&[("instance", "10.0.0.2:9100")], 1.0),
+                ],
+            )
+            .with_range(
+                r#"max by (instance) (max_over_time(node_hwmon_temp_celsius{instance=~"10\\.0\\.0\\.1:9100"}[1m]))"#,
+                vec![RangeSeries {
+                    metric: HashMap::from([("instance".to_string(), "10.0.0.1:9100".to_string())]),
+                    values: vec![(1700000000.0, 72.0), (1700000060.0, 73.34)],
+                }],
+            );
+        // Only the requested node's instance is queried
+        let selector = r#"node_hwmon_temp_celsius{instance=~"10\\.0\\.0\\.1:9100"}"#;
+        for window in ["1m", "1h", "1d"] {
+            backend = backend.with_instant(format!("max_over_time({}[{}])", selector, window), readings.clone());
+        }
+        backend
+    }
+
+    #[tokio::test]
+    async fn node_detail_combines_readings_instances_and_sparkline() {
+        let detail = fetch_node(&state_with(backend()), "blade001", &NodeParams::default()).await.unwrap();
+
+        assert_eq!(detail.measurement.minutely_temperature, Some(73.3));
+        assert_eq!(detail.measurement.sensors.as_ref().unwrap().len(), 1);
+        assert_eq!(detail.configured_windows.hourly, "1h");
+        assert_eq!(detail.instances.len(), 1);
+        assert_eq!(detail.instances[0].instance, "10.0.0.1:9100");
+        assert_eq!(detail.instances[0].source, Source::ExporterPod);
+        let sparkline = detail.sparkline.unwrap();
+        assert_eq!(sparkline.step_seconds, 60);
+        assert_eq!(sparkline.points.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn unknown_nodes_are_not_found() {
+        let error = fetch_node(&state_with(backend()), "blade404", &NodeParams::default()).await.unwrap_err();
+
+        assert!(matches!(error, ApiError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn nodes_of_several_clusters_need_a_datasource() {
+        let mut config = Config::default();
+        for cluster in ["east", "west"] {
+            config.datasources.sources.insert(cluster.to_string(), format!("http://{}:8429", cluster));
+            config.federation.clusters.push(cluster.to_string());
+        }
+        let state = state_with_config(backend(), config);
+
+        let error = fetch_node(&state, "blade001", &NodeParams::default()).await.unwrap_err();
+        assert!(matches!(error, ApiError::Conflict(_)));
+
+        let east = NodeParams {
+            datasource: Some("east".to_string()),
+        };
+        let detail = fetch_node(&state, "blade001", &east).await.unwrap();
+        assert_eq!(detail.measurement.cluster.as_deref(), Some("east"));
+        assert_eq!(detail.instances.len(), 1);
+    }
+}
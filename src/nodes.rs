@@ -0,0 +1,458 @@
+use crate::backend::MetricsBackend;
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::promql::{Aggregation, Selector};
+use crate::state::AppState;
+use anyhow::Context;
+use axum::extract::{rejection::QueryRejection, Query, State};
+use axum::response::Json;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::{debug, info, warn};
+
+/// Where the node name of an instance came from, in the order the sources are tried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    // `nodename` of node_uname_info scraped from the instance itself
+    Uname,
+    // An address of the node in kube_node_status_addresses, e.g. a hostNetwork exporter
+    NodeAddress,
+    // IP of an exporter pod from kube_pod_info on `nodes.exporter_port`
+    ExporterPod,
+    // Entry of `nodes.mapping_file`
+    Static,
+    // Nothing matched, the instance itself is used as the name
+    Instance,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Uname => write!(f, "node_uname_info"),
+            Source::NodeAddress => write!(f, "kube_node_status_addresses"),
+            Source::ExporterPod => write!(f, "kube_pod_info"),
+            Source::Static => write!(f, "mapping file"),
+            Source::Instance => write!(f, "instance"),
+        }
+    }
+}
+
+/// Resolves scrape instances (`host:port`) to node names through a chain of sources. Instances no source knows
+/// keep their own name, so unmapped instances never collapse into one node.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NodeMap {
+    // Exact instances
+    uname: HashMap<String, String>,
+    // Hosts, matched on any port
+    addresses: HashMap<String, String>,
+    // Exact `pod_ip:exporter_port` instances
+    exporter_pods: HashMap<String, String>,
+    // Instances or hosts
+    static_map: HashMap<String, String>,
+}
+
+impl NodeMap {
+    /// Fetches every source; a failing source keeps its entries from `previous`, the others still resolve.
+    pub async fn fetch(backend: &dyn MetricsBackend, config: &Config, previous: Option<&NodeMap>) -> Self {
+        let now = Utc::now();
+        let uname_query = config.metric_selector("node_uname_info").to_string();
+        let addresses_query = Selector::new("kube_node_status_addresses").to_string();
+        let pods_selector = config.exporter_pods_selector().to_string();
+        let (uname, addresses, pods) = tokio::join!(
+            backend.instant_query(&uname_query),
+            backend.instant_query(&addresses_query),
+            backend.series(&pods_selector, now - Duration::minutes(5), now)
+        );
+
+        let mut map = previous.cloned().unwrap_or_default();
+        match uname {
+            Ok(vector) => {
+                map.uname.clear();
+                for sample in vector.samples {
+                    if let (Some(instance), Some(node)) = (sample.metric.get("instance"), sample.metric.get("nodename")) {
+                        map.uname.insert(instance.clone(), node.clone());
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to fetch node_uname_info for node mapping: {:#}", e),
+        }
+        match addresses {
+            Ok(vector) => {
+                map.addresses.clear();
+                for sample in vector.samples {
+                    if let (Some(address), Some(node)) = (sample.metric.get("address"), sample.metric.get("node")) {
+                        map.addresses.insert(address.clone(), node.clone());
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to fetch kube_node_status_addresses for node mapping: {:#}", e),
+        }
+        match pods {
+            Ok(pods) => {
+                map.exporter_pods.clear();
+                for labels in pods {
+                    if let (Some(pod_ip), Some(node)) = (labels.get("pod_ip"), labels.get("node")) {
+                        let instance = format!("{}:{}", pod_ip, config.nodes.exporter_port);
+                        map.exporter_pods.insert(instance, node.clone());
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to fetch exporter pods for node mapping: {:#}", e),
+        }
+        if let Some(path) = &config.nodes.mapping_file {
+            match load_mapping_file(path) {
+                Ok(static_map) => map.static_map = static_map,
+                Err(e) => warn!("Failed to load node mapping file: {:#}", e),
+            }
+        }
+        debug!(
+            "Node mapping sources: {} uname, {} addresses, {} exporter pods, {} static",
+            map.uname.len(),
+            map.addresses.len(),
+            map.exporter_pods.len(),
+            map.static_map.len()
+        );
+        map
+    }
+
+    /// Name of the node behind `instance` and the source that named it.
+    pub fn resolve(&self, instance: &str) -> (String, Source) {
+        let host = host(instance);
+        let found = self
+            .uname
+            .get(instance)
+            .map(|node| (node, Source::Uname))
+            .or_else(|| self.addresses.get(host).map(|node| (node, Source::NodeAddress)))
+            .or_else(|| self.exporter_pods.get(instance).map(|node| (node, Source::ExporterPod)))
+            .or_else(|| {
+                let node = self.static_map.get(instance).or_else(|| self.static_map.get(host))?;
+                Some((node, Source::Static))
+            });
+        match found {
+            Some((node, source)) => (node.clone(), source),
+            None => (instance.to_string(), Source::Instance),
+        }
+    }
+
+    pub fn node_for(&self, instance: &str) -> String {
+        self.resolve(instance).0
+    }
+}
+
+/// How one scraped instance is named.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InstanceMapping {
+    pub instance: String,
+    pub node: String,
+    pub source: Source,
+}
+
+struct Snapshot {
+    map: Arc<NodeMap>,
+    // Instances currently reporting temperatures, by instance
+    instances: Arc<BTreeMap<String, InstanceMapping>>,
+    refreshed: Instant,
+    refreshed_at: DateTime<Utc>,
+}
+
+/// Node mapping of one backend, shared by all requests and refreshed in the background.
+pub struct NodeDirectory {
+    backend: Arc<dyn MetricsBackend>,
+    config: Arc<Config>,
+    refresh_interval: std::time::Duration,
+    current: RwLock<Option<Arc<Snapshot>>>,
+    // Serializes refreshes, so requests arriving together share one
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+impl NodeDirectory {
+    pub fn new(backend: Arc<dyn MetricsBackend>, config: Arc<Config>) -> anyhow::Result<Self> {
+        Ok(Self {
+            backend,
+            refresh_interval: config.node_refresh_interval()?,
+            config,
+            current: RwLock::new(None),
+            refreshing: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// The current mapping. Requests only load it themselves before the first refresh or when the background
+    /// refresh fell behind.
+    pub async fn map(&self) -> Arc<NodeMap> {
+        if let Some(snapshot) = self.fresh() {
+            return snapshot.map.clone();
+        }
+        let _refreshing = self.refreshing.lock().await;
+        match self.fresh() {
+            Some(snapshot) => snapshot.map.clone(),
+            None => self.reload().await.map.clone(),
+        }
+    }
+
+    pub async fn refresh(&self) {
+        let _refreshing = self.refreshing.lock().await;
+        self.reload().await;
+    }
+
+    fn fresh(&self) -> Option<Arc<Snapshot>> {
+        let current = self.current.read().unwrap();
+        current
+            .as_ref()
+            .filter(|snapshot| snapshot.refreshed.elapsed() < self.refresh_interval * 2)
+            .cloned()
+    }
+
+    async fn reload(&self) -> Arc<Snapshot> {
+        let previous = self.current.read().unwrap().clone();
+        let instances_query = Aggregation::Count.by(&["instance"], self.config.temperature_selector()).to_string();
+        let (map, instances) = tokio::join!(
+            NodeMap::fetch(self.backend.as_ref(), &self.config, previous.as_ref().map(|p| p.map.as_ref())),
+            self.backend.instant_query(&instances_query)
+        );
+        let instances: Vec<String> = match instances {
+            Ok(vector) => vector.samples.into_iter().filter_map(|sample| sample.metric.get("instance").cloned()).collect(),
+            Err(e) => {
+                warn!("Failed to list reporting instances, keeping the previous ones: {:#}", e);
+                previous.iter().flat_map(|p| p.instances.keys().cloned()).collect()
+            }
+        };
+        let instances: BTreeMap<String, InstanceMapping> = instances
+            .into_iter()
+            .map(|instance| {
+                let (node, source) = map.resolve(&instance);
+                (instance.clone(), InstanceMapping { instance, node, source })
+            })
+            .collect();
+        log_changes(previous.as_ref().map(|p| p.instances.as_ref()), &instances);
+
+        let snapshot = Arc::new(Snapshot {
+            map: Arc::new(map),
+            instances: Arc::new(instances),
+            refreshed: Instant::now(),
+            refreshed_at: Utc::now(),
+        });
+        *self.current.write().unwrap() = Some(snapshot.clone());
+        snapshot
+    }
+
+    /// Every reporting instance with its node, unmapped ones listed separately as well.
+    pub async fn report(&self) -> MappingReport {
+        self.map().await;
+        let snapshot = self.current.read().unwrap().clone().expect("map() loads a snapshot");
+        MappingReport {
+            refreshed_at: snapshot.refreshed_at,
+            instances: snapshot.instances.values().cloned().collect(),
+            unmapped: snapshot
+                .instances
+                .values()
+                .filter(|mapping| mapping.source == Source::Instance)
+                .map(|mapping| mapping.instance.clone())
+                .collect(),
+        }
+    }
+
+    /// Every instance currently reporting temperatures.
+    pub async fn instances(&self) -> Vec<InstanceMapping> {
+        self.map().await;
+        let snapshot = self.current.read().unwrap().clone().expect("map() loads a snapshot");
+        snapshot.instances.values().cloned().collect()
+    }
+
+    /// Instances with a temperature within `window`, resolved with the current mapping. Unlike `instances()` this
+    /// includes instances that stopped reporting minutes ago and is not limited to the last refresh, so node filters
+    /// keep a silent node's hourly and daily values and pick up instances that just appeared.
+    pub async fn instances_within(&self, window: std::time::Duration) -> Vec<InstanceMapping> {
+        let query = self.config.instances_query(window).to_string();
+        let (map, recent) = tokio::join!(self.map(), self.backend.instant_query(&query));
+        let mut instances: BTreeSet<String> = match recent {
+            Ok(vector) => vector.samples.into_iter().filter_map(|sample| sample.metric.get("instance").cloned()).collect(),
+            Err(e) => {
+                warn!("Failed to list instances of the last {:?}, using the reporting ones: {:#}", window, e);
+                BTreeSet::new()
+            }
+        };
+        let snapshot = self.current.read().unwrap().clone().expect("map() loads a snapshot");
+        instances.extend(snapshot.instances.keys().cloned());
+        instances
+            .into_iter()
+            .map(|instance| {
+                let (node, source) = map.resolve(&instance);
+                InstanceMapping { instance, node, source }
+            })
+            .collect()
+    }
+
+    /// Reporting instances that resolve to `node`.
+    pub async fn instances_of(&self, node: &str) -> Vec<InstanceMapping> {
+        self.instances().await.into_iter().filter(|mapping| mapping.node == node).collect()
+    }
+}
+
+// Logs what changed since the previous refresh, so a stable mapping stays quiet
+fn log_changes(previous: Option<&BTreeMap<String, InstanceMapping>>, current: &BTreeMap<String, InstanceMapping>) {
+    let Some(previous) = previous else {
+        let unmapped: Vec<&str> = current
+            .values()
+            .filter(|mapping| mapping.source == Source::Instance)
+            .map(|mapping| mapping.instance.as_str())
+            .collect();
+        info!("Node mapping loaded: {} instance(s), {} unmapped", current.len(), unmapped.len());
+        for instance in unmapped {
+            warn!("No node name found for instance {}, reporting it under its own name", instance);
+        }
+        return;
+    };
+    for (instance, mapping) in current {
+        match previous.get(instance) {
+            None => info!("Instance {} appeared, mapped to {} via {}", instance, mapping.node, mapping.source),
+            Some(old) if old != mapping => info!(
+                "Instance {} now maps to {} via {}, was {} via {}",
+                instance, mapping.node, mapping.source, old.node, old.source
+            ),
+            Some(_) => {}
+        }
+    }
+    for instance in previous.keys().filter(|instance| !current.contains_key(*instance)) {
+        info!("Instance {} stopped reporting temperatures", instance);
+    }
+}
+
+/// Refreshes the node mapping of one datasource every `nodes.refresh_interval`. Spawned for every datasource requests
+/// can reach; a mapping older than twice the interval is reloaded on demand, so one that is never refreshed in the
+/// background still works.
+pub async fn run(directory: Arc<NodeDirectory>) {
+    let mut ticker = tokio::time::interval(directory.refresh_interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        directory.refresh().await;
+    }
+}
+
+/// Response of `GET /api/nodes/mapping`.
+#[derive(Debug, Serialize)]
+pub struct MappingReport {
+    refreshed_at: DateTime<Utc>,
+    instances: Vec<InstanceMapping>,
+    // Instances no source could name, reported under the instance itself
+    unmapped: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct MappingParams {
+    datasource: Option<String>,
+}
+
+pub async fn get_node_mapping(
+    State(state): State<AppState>,
+    params: Result<Query<MappingParams>, QueryRejection>,
+) -> Result<Json<MappingReport>, ApiError> {
+    let Query(params) = params?;
+    let datasource = state.datasource(params.datasource.as_deref())?;
+    Ok(Json(datasource.nodes.report().await))
+}
+
+/// Reads a TOML file of `"instance or host" = "node"` entries.
+pub fn load_mapping_file(path: &str) -> anyhow::Result<HashMap<String, String>> {
+    let contents = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path))?;
+    let entries: BTreeMap<String, String> = toml::from_str(&contents).with_context(|| format!("failed to parse {}", path))?;
+    Ok(entries.into_iter().collect())
+}
+
+// Host part of an instance, `[::1]:9100` -> `::1`
+fn host(instance: &str) -> &str {
+    if let Some(rest) = instance.strip_prefix('[') {
+        return rest.split_once(']').map_or(rest, |(host, _)| host);
+    }
+    match instance.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') && port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => instance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory::{sample, InMemoryBackend};
+    use crate::test_support::{pod, NODE_EXPORTER_PODS};
+
+    #[tokio::test]
+    async fn sources_are_tried_in_order() {
+        let backend = InMemoryBackend::new()
+            .with_instant(
+                "node_uname_info",
+                vec![sample(&[("instance", "10.0.0.1:9100"), ("nodename", "blade001")], 1.0)],
+            )
+            .with_instant(
+                "kube_node_status_addresses",
+                vec![
+                    sample(&[("node", "blade002"), ("address", "192.168.1.2"), ("type", "InternalIP")], 1.0),
+                    // Loses against node_uname_info
+                    sample(&[("node", "wrong"), ("address", "10.0.0.1"), ("type", "InternalIP")], 1.0),
+                ],
+            )
+            .with_series(NODE_EXPORTER_PODS, vec![pod("node-exporter-c", "10.0.0.3", "blade003")]);
+
+        let map = NodeMap::fetch(&backend, &Config::default(), None).await;
+
+        assert_eq!(map.resolve("10.0.0.1:9100"), ("blade001".to_string(), Source::Uname));
+        assert_eq!(map.resolve("192.168.1.2:9100"), ("blade002".to_string(), Source::NodeAddress));
+        assert_eq!(map.resolve("192.168.1.2:9256"), ("blade002".to_string(), Source::NodeAddress));
+        assert_eq!(map.resolve("10.0.0.3:9100"), ("blade003".to_string(), Source::ExporterPod));
+        // Exporter pods only match on the configured port
+        assert_eq!(map.resolve("10.0.0.3:8080"), ("10.0.0.3:8080".to_string(), Source::Instance));
+    }
+
+    #[tokio::test]
+    async fn failing_source_keeps_previous_entries() {
+        let previous = NodeMap {
+            uname: HashMap::from([("10.0.0.1:9100".to_string(), "blade001".to_string())]),
+            addresses: HashMap::from([("10.0.0.2".to_string(), "gone".to_string())]),
+            ..NodeMap::default()
+        };
+        let backend = InMemoryBackend::new().with_error("node_uname_info", "unavailable", "storage is down");
+
+        let map = NodeMap::fetch(&backend, &Config::default(), Some(&previous)).await;
+
+        assert_eq!(map.node_for("10.0.0.1:9100"), "blade001");
+        // The address lookup answered, without that node
+        assert_eq!(map.node_for("10.0.0.2:9100"), "10.0.0.2:9100");
+    }
+
+    #[tokio::test]
+    async fn directory_reports_unmapped_instances() {
+        let backend = InMemoryBackend::new()
+            .with_instant(
+                "count by (instance) (node_hwmon_temp_celsius)",
+                vec![sample(&[("instance", "10.0.0.1:9100")], 3.0), sample(&[("instance", "10.0.0.9:9100")], 2.0)],
+            )
+            .with_series(NODE_EXPORTER_PODS, vec![pod("node-exporter-a", "10.0.0.1", "blade001")]);
+        let directory = NodeDirectory::new(Arc::new(backend), Arc::new(Config::default())).unwrap();
+
+        let report = directory.report().await;
+
+        assert_eq!(report.instances.len(), 2);
+        assert_eq!(report.instances[0].node, "blade001");
+        assert_eq!(report.instances[0].source, Source::ExporterPod);
+        assert_eq!(report.unmapped, vec!["10.0.0.9:9100".to_string()]);
+    }
+
+    #[test]
+    fn unmapped_instances_keep_distinct_names() {
+        let map = NodeMap {
+            static_map: HashMap::from([("10.0.0.9".to_string(), "blade009".to_string())]),
+            ..NodeMap::default()
+        };
+
+        assert_eq!(map.resolve("10.0.0.9:9100"), ("blade009".to_string(), Source::Static));
+        assert_eq!(map.node_for("10.0.0.7:9100"), "10.0.0.7:9100");
+        assert_eq!(map.node_for("10.0.0.8:9100"), "10.0.0.8:9100");
+        assert_eq!(host("[fd00::1]:9100"), "fd00::1");
+        assert_eq!(host("fd00::1"), "fd00::1");
+    }
+}
@@ -0,0 +1,124 @@
+use crate::backend::MetricsBackend;
+use crate::config::Config;
+use crate::thresholds::Status;
+use crate::windows::{MissingReason, MissingWindows};
+use crate::nodes::NodeMap;
+use crate::promql::Selector;
+use crate::TemperatureMeasurement;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+// Nodes registered in the cluster, exported by kube-state-metrics
+const KUBE_NODE_INFO: &str = "kube_node_info";
+
+/// When each node last reported a temperature, and which nodes the cluster knows about.
+#[derive(Debug, Default)]
+pub struct Presence {
+    // None when the query failed, so silence cannot be judged
+    last_seen: Option<HashMap<String, DateTime<Utc>>>,
+    // None when kube_node_info could not be fetched, so no node is assumed missing
+    known_nodes: Option<BTreeSet<String>>,
+}
+
+impl Presence {
+    /// Fetches the last sample time per node and the cluster's node list; failures only lose the offline
+    /// detection, so they are logged.
+    pub async fn fetch(backend: &dyn MetricsBackend, config: &Config, node_map: &NodeMap) -> Self {
+        let fetch_last_seen = async { backend.instant_query(&config.last_seen_query()?.to_string()).await };
+        let known_nodes_query = Selector::new(KUBE_NODE_INFO).to_string();
+        let (last_seen, known_nodes) = tokio::join!(fetch_last_seen, backend.instant_query(&known_nodes_query));
+
+        let mut presence = Self::default();
+        match last_seen {
+            Ok(vector) => {
+                let last_seen = presence.last_seen.insert(HashMap::new());
+                for sample in vector.samples {
+                    let Some(instance) = sample.metric.get("instance") else {
+                        continue;
+                    };
+                    let Some(seen) = DateTime::from_timestamp(sample.value as i64, 0) else {
+                        continue;
+                    };
+                    let node = node_map.node_for(instance);
+                    let latest = last_seen.entry(node).or_insert(seen);
+                    *latest = (*latest).max(seen);
+                }
+            }
+            Err(e) => warn!("Failed to fetch last seen timestamps: {:#}", e),
+        }
+        match known_nodes {
+            Ok(vector) => {
+                presence.known_nodes = Some(
+                    vector
+                        .samples
+                        .into_iter()
+                        .filter_map(|sample| sample.metric.get("node").cloned())
+                        .collect(),
+                );
+            }
+            Err(e) => warn!("Failed to fetch {}, not checking for silent nodes: {:#}", KUBE_NODE_INFO, e),
+        }
+        presence
+    }
+
+    /// Drops the cluster's node list, for requests whose label filters may exclude registered nodes.
+    pub fn without_known_nodes(self) -> Self {
+        Self {
+            known_nodes: None,
+            ..self
+        }
+    }
+
+    /// Sets `last_seen` on every measurement, marks nodes without a current reading that have been silent for
+    /// longer than `offline_after` as offline, and adds known nodes that reported nothing at all. Without last
+    /// seen data nothing is changed.
+    pub fn apply(
+        &self,
+        measurements: &mut HashMap<String, TemperatureMeasurement>,
+        offline_after: Duration,
+        now: DateTime<Utc>,
+    ) {
+        let Some(last_seen) = &self.last_seen else {
+            return;
+        };
+        for measurement in measurements.values_mut() {
+            measurement.last_seen = last_seen.get(&measurement.node).copied();
+            // A current reading means the node is up, whatever the (possibly cached) last_seen says
+            let minutely_failed = measurement.missing.get("minutely") == Some(&MissingReason::QueryFailed);
+            if measurement.minutely_temperature.is_some() || minutely_failed {
+                continue;
+            }
+            let silent_for = measurement.last_seen.map(|seen| (now - seen).to_std().unwrap_or_default());
+            if silent_for.is_none_or(|silent_for| silent_for > offline_after) {
+                measurement.status = Status::Offline;
+            }
+        }
+
+        for node in self.known_nodes.iter().flatten() {
+            if measurements.contains_key(node) {
+                continue;
+            }
+            debug!("Node {} is registered but reported no temperatures", node);
+            measurements.insert(
+                node.clone(),
+                TemperatureMeasurement {
+                    node: node.clone(),
+                    cluster: None,
+                    minutely_temperature: None,
+                    hourly_temperature: None,
+                    daily_temperature: None,
+                    missing: ["minutely", "hourly", "daily"]
+                        .into_iter()
+                        .map(|window| (window.to_string(), MissingReason::NoData))
+                        .collect::<MissingWindows>(),
+                    last_seen: last_seen.get(node).copied(),
+                    status: Status::Offline,
+                    windows: None,
+                    sensors: None,
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use std::fmt;
+use std::time::Duration;
+
+/// PromQL label matching operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchOp {
+    Equal,
+    NotEqual,
+    Regex,
+    NotRegex,
+}
+
+impl fmt::Display for MatchOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Equal => "=",
+            Self::NotEqual => "!=",
+            Self::Regex => "=~",
+            Self::NotRegex => "!~",
+        })
+    }
+}
+
+/// A label matcher such as `job="node-exporter"`, rendered with the value escaped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelMatcher {
+    pub name: String,
+    pub op: MatchOp,
+    pub value: String,
+}
+
+impl LabelMatcher {
+    pub fn new(name: impl Into<String>, op: MatchOp, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            op,
+            value: value.into(),
+        }
+    }
+
+    pub fn equal(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self::new(name, MatchOp::Equal, value)
+    }
+
+    /// Parses `name=value`, `name!=value`, `name=~regex` or `name!~regex`.
+    pub fn parse(matcher: &str) -> Result<Self, String> {
+        let operators = [
+            ("!=", MatchOp::NotEqual),
+            ("=~", MatchOp::Regex),
+            ("!~", MatchOp::NotRegex),
+            ("=", MatchOp::Equal),
+        ];
+        let (name, op, value) = operators
+            .into_iter()
+            .filter_map(|(token, op)| matcher.find(token).map(|at| (at, token, op)))
+            // The leftmost operator wins, two-character ones before `=` at the same position
+            .min_by_key(|(at, token, _)| (*at, usize::MAX - token.len()))
+            .map(|(at, token, op)| (matcher[..at].trim(), op, &matcher[at + token.len()..]))
+            .ok_or_else(|| format!("invalid label matcher {:?}, expected name=value", matcher))?;
+        if !is_valid_label_name(name) {
+            return Err(format!("invalid label name {:?} in matcher {:?}", name, matcher));
+        }
+        if name.starts_with("__") {
+            return Err(format!("label {:?} is reserved", name));
+        }
+        Ok(Self::new(name, op, value))
+    }
+}
+
+impl fmt::Display for LabelMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Names come from configuration or LabelMatcher::parse; anything else could not be rendered safely
+        debug_assert!(is_valid_label_name(&self.name), "invalid label name {:?}", self.name);
+        write!(f, "{}{}\"{}\"", self.name, self.op, escape_label_value(&self.value))
+    }
+}
+
+/// An instant vector selector such as `node_hwmon_temp_celsius{job="node-exporter"}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    metric: String,
+    matchers: Vec<LabelMatcher>,
+}
+
+impl Selector {
+    pub fn new(metric: impl Into<String>) -> Self {
+        Self {
+            metric: metric.into(),
+            matchers: Vec::new(),
+        }
+    }
+
+    pub fn with(mut self, matcher: LabelMatcher) -> Self {
+        self.matchers.push(matcher);
+        self
+    }
+
+    pub fn with_all(mut self, matchers: impl IntoIterator<Item = LabelMatcher>) -> Self {
+        self.matchers.extend(matchers);
+        self
+    }
+
+    /// `selector[range]`
+    pub fn range(self, range: Duration) -> RangeVector {
+        RangeVector {
+            expr: Box::new(Expr::Selector(self)),
+            range,
+            step: None,
+        }
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut matchers: Vec<String> = self.matchers.iter().map(ToString::to_string).collect();
+        // A metric name that is not a valid identifier can only be matched through __name__
+        if is_valid_metric_name(&self.metric) {
+            f.write_str(&self.metric)?;
+        } else {
+            matchers.insert(0, format!("__name__=\"{}\"", escape_label_value(&self.metric)));
+        }
+        if !matchers.is_empty() {
+            write!(f, "{{{}}}", matchers.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// A range vector: a selector with a range, or a subquery with a resolution.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeVector {
+    expr: Box<Expr>,
+    range: Duration,
+    // Resolution of a subquery, None for a plain selector range
+    step: Option<Duration>,
+}
+
+impl fmt::Display for RangeVector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.expr.as_ref(), self.step) {
+            (Expr::Selector(selector), None) => write!(f, "{}[{}]", selector, format_duration(self.range)),
+            (expr @ (Expr::Selector(_) | Expr::Call { .. }), Some(step)) => {
+                write!(f, "{}[{}:{}]", expr, format_duration(self.range), format_duration(step))
+            }
+            (expr, step) => write!(
+                f,
+                "({})[{}:{}]",
+                expr,
+                format_duration(self.range),
+                step.map(format_duration).unwrap_or_default()
+            ),
+        }
+    }
+}
+
+/// The `*_over_time` range functions, with their scalar parameter where they take one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RangeFunction {
+    Max,
+    Min,
+    Avg,
+    // Newest sample within the range
+    Last,
+    // Quantile between 0 and 1
+    Quantile(f64),
+}
+
+impl RangeFunction {
+    pub fn apply(self, range: RangeVector) -> Expr {
+        Expr::OverTime { function: self, range }
+    }
+}
+
+/// Functions of one instant vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Timestamp,
+}
+
+impl Function {
+    pub fn apply(self, argument: impl Into<Expr>) -> Expr {
+        Expr::Call {
+            function: self,
+            argument: Box::new(argument.into()),
+        }
+    }
+}
+
+/// Aggregation operators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    Max,
+    Count,
+}
+
+impl Aggregation {
+    /// `op by (labels) (argument)`
+    pub fn by(self, labels: &[&str], argument: impl Into<Expr>) -> Expr {
+        Expr::Aggregate {
+            op: self,
+            by: labels.iter().map(|label| label.to_string()).collect(),
+            argument: Box::new(argument.into()),
+        }
+    }
+}
+
+/// A PromQL expression. Label values are escaped and durations rendered from `Duration`s, so nothing a client
+/// passes in can change the structure of the query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Selector(Selector),
+    OverTime { function: RangeFunction, range: RangeVector },
+    Call { function: Function, argument: Box<Expr> },
+    Aggregate { op: Aggregation, by: Vec<String>, argument: Box<Expr> },
+}
+
+impl Expr {
+    /// `expr[range:step]`
+    pub fn subquery(self, range: Duration, step: Duration) -> RangeVector {
+        RangeVector {
+            expr: Box::new(self),
+            range,
+            step: Some(step),
+        }
+    }
+}
+
+impl From<Selector> for Expr {
+    fn from(selector: Selector) -> Self {
+        Self::Selector(selector)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Selector(selector) => selector.fmt(f),
+            Self::OverTime { function, range } => match function {
+                RangeFunction::Max => write!(f, "max_over_time({})", range),
+                RangeFunction::Min => write!(f, "min_over_time({})", range),
+                RangeFunction::Avg => write!(f, "avg_over_time({})", range),
+                RangeFunction::Last => write!(f, "last_over_time({})", range),
+                RangeFunction::Quantile(quantile) => write!(f, "quantile_over_time({}, {})", quantile, range),
+            },
+            Self::Call { function, argument } => match function {
+                Function::Timestamp => write!(f, "timestamp({})", argument),
+            },
+            Self::Aggregate { op, by, argument } => {
+                let op = match op {
+                    Aggregation::Max => "max",
+                    Aggregation::Count => "count",
+                };
+                debug_assert!(by.iter().all(|label| is_valid_label_name(label)), "invalid grouping {:?}", by);
+                write!(f, "{} by ({}) ({})", op, by.join(", "), argument)
+            }
+        }
+    }
+}
+
+/// Renders a duration in the largest PromQL unit that divides it, e.g. `5m` or `90s`.
+pub fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if !millis.is_multiple_of(1000) {
+        return format!("{}ms", millis);
+    }
+    let seconds = duration.as_secs();
+    [(86_400, "d"), (3_600, "h"), (60, "m")]
+        .into_iter()
+        .find(|(unit, _)| seconds > 0 && seconds.is_multiple_of(*unit))
+        .map(|(unit, suffix)| format!("{}{}", seconds / unit, suffix))
+        .unwrap_or_else(|| format!("{}s", seconds))
+}
+
+pub fn is_valid_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+pub fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Escapes a value for a double-quoted PromQL string.
+pub fn escape_label_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selector() -> Selector {
+        Selector::new("node_hwmon_temp_celsius").with(LabelMatcher::equal("job", "node-exporter"))
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label_value(r#"C:\temp"#), r#"C:\\temp"#);
+        assert_eq!(escape_label_value(r#"say "hi""#), r#"say \"hi\""#);
+        assert_eq!(escape_label_value("a\nb\r\tc\u{0}"), r#"a\nb\r\tc\u0000"#);
+        assert_eq!(escape_label_value("blade-ü{}"), "blade-ü{}");
+        // A trailing backslash must not swallow the closing quote
+        assert_eq!(LabelMatcher::equal("node", r"x\").to_string(), r#"node="x\\""#);
+    }
+
+    #[test]
+    fn injected_values_stay_inside_the_string() {
+        let selector = Selector::new("node_hwmon_temp_celsius").with(LabelMatcher::equal("node", r#"x"} or vector(1) #"#));
+
+        assert_eq!(selector.to_string(), r#"node_hwmon_temp_celsius{node="x\"} or vector(1) #"}"#);
+    }
+
+    #[test]
+    fn invalid_metric_names_are_matched_by_name() {
+        let selector = Selector::new(r#"up{job="x"}"#).with(LabelMatcher::new("instance", MatchOp::NotRegex, "a|b"));
+
+        assert_eq!(selector.to_string(), r#"{__name__="up{job=\"x\"}",instance!~"a|b"}"#);
+        assert_eq!(Selector::new("kube_node_info").to_string(), "kube_node_info");
+    }
+
+    #[test]
+    fn matchers_are_parsed() {
+        let matcher = LabelMatcher::parse(r#"job!~node"exp\orter"#).unwrap();
+        assert_eq!(matcher.op, MatchOp::NotRegex);
+        assert_eq!(matcher.to_string(), r#"job!~"node\"exp\\orter""#);
+        assert_eq!(LabelMatcher::parse("cluster=a=b").unwrap().value, "a=b");
+        assert_eq!(LabelMatcher::parse("rack=~r1|r2").unwrap().op, MatchOp::Regex);
+        assert!(LabelMatcher::parse("no operator").is_err());
+        assert!(LabelMatcher::parse("bad-name=x").is_err());
+        assert!(LabelMatcher::parse("__name__=up").is_err());
+    }
+
+    #[test]
+    fn expressions_render_as_promql() {
+        let minute = Duration::from_secs(60);
+        let max = RangeFunction::Max.apply(selector().range(minute));
+        let p95 = RangeFunction::Quantile(0.95).apply(selector().range(Duration::from_secs(300)));
+        let last_seen = Aggregation::Max.by(
+            &["instance"],
+            RangeFunction::Max.apply(Function::Timestamp.apply(selector()).subquery(Duration::from_secs(3_600), minute)),
+        );
+
+        assert_eq!(max.to_string(), r#"max_over_time(node_hwmon_temp_celsius{job="node-exporter"}[1m])"#);
+        assert_eq!(p95.to_string(), r#"quantile_over_time(0.95, node_hwmon_temp_celsius{job="node-exporter"}[5m])"#);
+        assert_eq!(
+            last_seen.to_string(),
+            r#"max by (instance) (max_over_time(timestamp(node_hwmon_temp_celsius{job="node-exporter"})[1h:1m]))"#
+        );
+        assert_eq!(
+            Aggregation::Count.by(&["instance"], selector()).to_string(),
+            r#"count by (instance) (node_hwmon_temp_celsius{job="node-exporter"})"#
+        );
+    }
+
+    #[test]
+    fn durations_use_the_largest_unit() {
+        assert_eq!(format_duration(Duration::from_secs(86_400)), "1d");
+        assert_eq!(format_duration(Duration::from_secs(7 * 86_400)), "7d");
+        assert_eq!(format_duration(Duration::from_secs(300)), "5m");
+        assert_eq!(format_duration(Duration::from_secs(90)), "90s");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1500ms");
+    }
+}
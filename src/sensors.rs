@@ -0,0 +1,113 @@
+use crate::backend::{InstantSample, MetricsBackend};
+use crate::config::Config;
+use crate::thresholds::{Headroom, Limits, Status};
+use crate::windows::{MissingWindows, WindowValues};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Identifies one hwmon sensor: node-exporter exposes every reading with `chip` and `sensor` labels.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SensorKey {
+    pub instance: String,
+    pub chip: String,
+    pub sensor: String,
+}
+
+impl SensorKey {
+    pub fn from_sample(sample: &InstantSample) -> Option<Self> {
+        Some(Self {
+            instance: sample.metric.get("instance")?.clone(),
+            chip: sample.metric.get("chip").cloned().unwrap_or_default(),
+            sensor: sample.metric.get("sensor").cloned().unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SensorMeasurement {
+    // Scrape target of the reading; a node scraped under several addresses reports its sensors once per instance
+    pub instance: String,
+    pub chip: String,
+    // Driver name from node_hwmon_chip_names, e.g. "coretemp" or "nvme"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chip_name: Option<String>,
+    pub sensor: String,
+    // Human readable label from node_hwmon_sensor_label, e.g. "Package id 0" or "Composite"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub minutely_temperature: Option<f64>,
+    pub hourly_temperature: Option<f64>,
+    pub daily_temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "MissingWindows::is_empty")]
+    pub missing: MissingWindows,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub windows: Option<WindowValues>,
+    #[serde(default)]
+    pub status: Status,
+    // Limits the status was judged against
+    #[serde(default, skip_serializing_if = "Limits::is_empty")]
+    pub thresholds: Limits,
+    // Distance of the minutely reading to the critical limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub headroom: Option<Headroom>,
+}
+
+/// Descriptive metadata joined onto the raw sensor readings.
+#[derive(Debug, Default)]
+pub struct SensorLabels {
+    labels: HashMap<SensorKey, String>,
+    chip_names: HashMap<(String, String), String>,
+}
+
+impl SensorLabels {
+    /// Fetches sensor labels and chip names; missing metadata only degrades the output, so errors are logged.
+    pub async fn fetch(backend: &dyn MetricsBackend, config: &Config) -> Self {
+        let label_query = config.metric_selector("node_hwmon_sensor_label").to_string();
+        let chip_query = config.metric_selector("node_hwmon_chip_names").to_string();
+        let (labels, chip_names) = tokio::join!(
+            backend.instant_query(&label_query),
+            backend.instant_query(&chip_query)
+        );
+
+        let labels = labels.map(|vector| vector.samples).unwrap_or_else(|e| {
+            warn!("Failed to fetch sensor labels: {}", e);
+            Vec::new()
+        });
+        let chip_names = chip_names.map(|vector| vector.samples).unwrap_or_else(|e| {
+            warn!("Failed to fetch chip names: {}", e);
+            Vec::new()
+        });
+
+        Self::from_samples(labels, chip_names)
+    }
+
+    fn from_samples(labels: Vec<InstantSample>, chip_names: Vec<InstantSample>) -> Self {
+        Self {
+            labels: labels
+                .into_iter()
+                .filter_map(|sample| {
+                    let label = sample.metric.get("label")?.clone();
+                    Some((SensorKey::from_sample(&sample)?, label))
+                })
+                .collect(),
+            chip_names: chip_names
+                .into_iter()
+                .filter_map(|sample| {
+                    Some((
+                        (sample.metric.get("instance")?.clone(), sample.metric.get("chip")?.clone()),
+                        sample.metric.get("chip_name")?.clone(),
+                    ))
+                })
+                .collect(),
+        }
+    }
+
+    pub fn label(&self, key: &SensorKey) -> Option<String> {
+        self.labels.get(key).cloned()
+    }
+
+    pub fn chip_name(&self, key: &SensorKey) -> Option<String> {
+        self.chip_names.get(&(key.instance.clone(), key.chip.clone())).cloned()
+    }
+}
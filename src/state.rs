@@ -0,0 +1,101 @@
+use crate::backend::{CachePolicy, CachingBackend, HttpBackend, MetricsBackend, RetryPolicy};
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::last_good::LastGood;
+use crate::live::LiveHub;
+use crate::nodes::NodeDirectory;
+use reqwest::Client;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A named upstream with its own node mapping.
+pub struct Datasource {
+    pub backend: Arc<dyn MetricsBackend>,
+    pub nodes: Arc<NodeDirectory>,
+}
+
+/// Shared state handed to every request handler.
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    // Every configured datasource by name, the default one included
+    pub datasources: Arc<BTreeMap<String, Datasource>>,
+    // Upper bound for answering a single API request
+    pub request_deadline: Duration,
+    // Pooled client shared by the upstream backends and outgoing notifications
+    pub client: Client,
+    // Broadcasts live snapshots to streaming clients
+    pub hub: Arc<LiveHub>,
+    // Last successful temperature responses, served while the upstream is down
+    pub last_good: Arc<LastGood>,
+}
+
+impl AppState {
+    pub fn new(config: Config) -> anyhow::Result<Self> {
+        // One client for all upstream calls so connections and TLS sessions are pooled
+        let client = Client::builder()
+            .connect_timeout(config.connect_timeout()?)
+            .timeout(config.request_timeout()?)
+            .pool_idle_timeout(config.pool_idle_timeout()?)
+            .tcp_keepalive(Duration::from_secs(60))
+            .build()?;
+        let retry = RetryPolicy {
+            max_retries: config.upstream.max_retries,
+            initial_backoff: config.retry_backoff()?,
+            max_backoff: config.max_retry_backoff()?,
+        };
+
+        let cache = config.cache.enabled.then(|| CachePolicy::from_config(&config)).transpose()?;
+        let upstream = |url: &str| -> Arc<dyn MetricsBackend> {
+            let backend = Arc::new(HttpBackend::new(client.clone(), url, retry.clone()));
+            match &cache {
+                Some(policy) => Arc::new(CachingBackend::new(backend, policy.clone())),
+                None => backend,
+            }
+        };
+
+        let config = Arc::new(config);
+        let datasources = config
+            .datasources()
+            .into_iter()
+            .map(|(name, url)| {
+                let backend = upstream(&url);
+                let nodes = Arc::new(NodeDirectory::new(backend.clone(), config.clone())?);
+                Ok((name, Datasource { backend, nodes }))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            datasources: Arc::new(datasources),
+            request_deadline: config.request_deadline()?,
+            client,
+            hub: Arc::new(LiveHub::new(&config)?),
+            last_good: Arc::new(LastGood::new(config.max_staleness()?)),
+            config,
+        })
+    }
+
+    pub fn default_datasource(&self) -> &Datasource {
+        &self.datasources[&self.config.datasources.default]
+    }
+
+    /// The datasource a request asked for with ?datasource=, the default one without. Other datasources are
+    /// only available with `datasources.allow_selection`, except federated clusters, whose data is served anyway.
+    pub fn datasource(&self, name: Option<&str>) -> Result<&Datasource, ApiError> {
+        let default = &self.config.datasources.default;
+        let Some(name) = name.filter(|name| name != default) else {
+            return Ok(self.default_datasource());
+        };
+        let federated = self.config.federation.clusters.iter().any(|cluster| cluster == name);
+        if !self.config.datasources.allow_selection && !federated {
+            return Err(ApiError::Forbidden(format!(
+                "selecting a datasource is disabled, only {} is available",
+                default
+            )));
+        }
+        self.datasources.get(name).ok_or_else(|| {
+            let names: Vec<&str> = self.datasources.keys().map(String::as_str).collect();
+            ApiError::BadRequest(format!("unknown datasource {:?}, expected one of {}", name, names.join(", ")))
+        })
+    }
+}
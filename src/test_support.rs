@@ -0,0 +1,51 @@
+use crate::backend::memory::InMemoryBackend;
+use crate::backend::MetricsBackend;
+use crate::config::Config;
+use crate::last_good::LastGood;
+use crate::live::LiveHub;
+use crate::nodes::NodeDirectory;
+use crate::state::{AppState, Datasource};
+use reqwest::Client;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Exporter pod selector of the node mapping with the default `nodes.exporter_pods`
+pub const NODE_EXPORTER_PODS: &str = r#"kube_pod_info{pod=~".*node-exporter.*"}"#;
+
+pub fn state_with(backend: InMemoryBackend) -> AppState {
+    state_with_config(backend, Config::default())
+}
+
+pub fn state_with_config(backend: InMemoryBackend, config: Config) -> AppState {
+    let backend: Arc<dyn MetricsBackend> = Arc::new(backend);
+    let config = Arc::new(config);
+    // Every configured datasource is served by the same in-memory backend
+    let datasources = config
+        .datasources()
+        .into_keys()
+        .map(|name| {
+            let nodes = Arc::new(NodeDirectory::new(backend.clone(), config.clone()).unwrap());
+            let datasource = Datasource {
+                backend: backend.clone(),
+                nodes,
+            };
+            (name, datasource)
+        })
+        .collect();
+    AppState {
+        hub: Arc::new(LiveHub::new(&config).unwrap()),
+        last_good: Arc::new(LastGood::new(config.max_staleness().unwrap())),
+        config,
+        datasources: Arc::new(datasources),
+        request_deadline: Duration::from_secs(5),
+        client: Client::new(),
+    }
+}
+
+pub fn pod(name: &str, ip: &str, node: &str) -> HashMap<String, String> {
+    [("pod", name), ("pod_ip", ip), ("node", node)]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
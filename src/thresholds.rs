@@ -0,0 +1,255 @@
+use crate::backend::MetricsBackend;
+use crate::config::{Config, SensorThresholds, ThresholdsConfig};
+use crate::sensors::SensorKey;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use tracing::warn;
+
+// Vendor limits exported by node-exporter's hwmon collector
+const HWMON_MAX_METRIC: &str = "node_hwmon_temp_max_celsius";
+const HWMON_CRIT_METRIC: &str = "node_hwmon_temp_crit_celsius";
+
+/// Health of a reading, ordered from best to worst so the worst of several can be taken with `max`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    // No threshold applies or there is no usable reading
+    #[default]
+    Unknown,
+    // The node stopped reporting temperatures, see `nodes.offline_after`
+    Offline,
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Ok => write!(f, "ok"),
+            Status::Unknown => write!(f, "unknown"),
+            Status::Offline => write!(f, "offline"),
+            Status::Warning => write!(f, "warning"),
+            Status::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/// Status of a node from the statuses of its sensors: the worst among the sensors that could be judged, so one
+/// sensor without limits (an NVMe composite, say) does not hide the others. `Unknown` only when none could be.
+pub fn worst_judged(statuses: impl IntoIterator<Item = Status>) -> Status {
+    statuses.into_iter().filter(|status| *status != Status::Unknown).max().unwrap_or(Status::Unknown)
+}
+
+/// Warning and critical temperatures in °C applying to one sensor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Limits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub critical: Option<f64>,
+}
+
+impl Limits {
+    pub fn new(warning: Option<f64>, critical: Option<f64>) -> Self {
+        Self { warning, critical }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.warning.is_none() && self.critical.is_none()
+    }
+
+    /// Fills limits missing here from `fallback`.
+    pub fn or(self, fallback: Limits) -> Limits {
+        Limits {
+            warning: self.warning.or(fallback.warning),
+            critical: self.critical.or(fallback.critical),
+        }
+    }
+
+    pub fn status(&self, value: f64) -> Status {
+        if !value.is_finite() || self.is_empty() {
+            return Status::Unknown;
+        }
+        if self.critical.is_some_and(|critical| value >= critical) {
+            Status::Critical
+        } else if self.warning.is_some_and(|warning| value >= warning) {
+            Status::Warning
+        } else {
+            Status::Ok
+        }
+    }
+}
+
+/// Distance of a reading from its critical limit.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Headroom {
+    pub degrees: f64,
+    // Remaining headroom as a share of the critical temperature
+    pub percent: f64,
+}
+
+impl Headroom {
+    pub fn to_critical(value: f64, limits: &Limits) -> Option<Self> {
+        let critical = limits.critical?;
+        if !value.is_finite() || critical <= 0.0 {
+            return None;
+        }
+        let degrees = critical - value;
+        Some(Self {
+            degrees: (degrees * 10.0).round() / 10.0,
+            percent: (degrees / critical * 1000.0).round() / 10.0,
+        })
+    }
+}
+
+/// Threshold evaluation for one request: configured thresholds, falling back to the hwmon limits of each chip.
+pub struct Thresholds<'a> {
+    config: &'a ThresholdsConfig,
+    hwmon: HashMap<SensorKey, Limits>,
+}
+
+impl<'a> Thresholds<'a> {
+    pub fn new(config: &'a ThresholdsConfig, hwmon: HashMap<SensorKey, Limits>) -> Self {
+        Self { config, hwmon }
+    }
+
+    /// Loads the hwmon max/crit limits when enabled; without them only configured thresholds apply,
+    /// so errors are logged rather than failing the request.
+    pub async fn fetch(backend: &dyn MetricsBackend, config: &'a Config) -> Self {
+        if !config.thresholds.hwmon_limits {
+            return Self::new(&config.thresholds, HashMap::new());
+        }
+
+        let max_query = config.metric_selector(HWMON_MAX_METRIC).to_string();
+        let crit_query = config.metric_selector(HWMON_CRIT_METRIC).to_string();
+        let (max, crit) = tokio::join!(backend.instant_query(&max_query), backend.instant_query(&crit_query));
+
+        let mut hwmon: HashMap<SensorKey, Limits> = HashMap::new();
+        for (result, is_critical) in [(max, false), (crit, true)] {
+            let samples = result.map(|vector| vector.samples).unwrap_or_else(|e| {
+                warn!("Failed to fetch hwmon temperature limits: {}", e);
+                Vec::new()
+            });
+            // Some drivers report 0 or absurd values for unsupported limits
+            for sample in samples.iter().filter(|s| s.value.is_finite() && s.value > 0.0 && s.value < 200.0) {
+                let Some(key) = SensorKey::from_sample(sample) else {
+                    continue;
+                };
+                let limits = hwmon.entry(key).or_default();
+                if is_critical {
+                    limits.critical = Some(sample.value);
+                } else {
+                    limits.warning = Some(sample.value);
+                }
+            }
+        }
+
+        Self::new(&config.thresholds, hwmon)
+    }
+
+    pub fn limits_for(&self, node: &str, sensor: &SensorKey, label: Option<&str>) -> Limits {
+        let configured = self.config.limits_for(node, &sensor.chip, &sensor.sensor, label);
+        configured.or(self.hwmon.get(sensor).copied().unwrap_or_default())
+    }
+}
+
+impl ThresholdsConfig {
+    /// Resolves the limits for one sensor: the first matching sensor rule, then the first matching node group,
+    /// then the global thresholds.
+    pub fn limits_for(&self, node: &str, chip: &str, sensor: &str, label: Option<&str>) -> Limits {
+        let sensor_limits = self
+            .sensors
+            .iter()
+            .find(|rule| rule.matches(node, chip, sensor, label))
+            .map(|rule| Limits::new(rule.warning, rule.critical))
+            .unwrap_or_default();
+        let group_limits = self
+            .groups
+            .iter()
+            .find(|group| group.nodes.iter().any(|pattern| glob_match(pattern, node)))
+            .map(|group| Limits::new(group.warning, group.critical))
+            .unwrap_or_default();
+
+        sensor_limits
+            .or(group_limits)
+            .or(Limits::new(self.warning, self.critical))
+    }
+
+    /// Sensor rules matching on `label` need node_hwmon_sensor_label to be fetched.
+    pub fn needs_sensor_labels(&self) -> bool {
+        self.sensors.iter().any(|rule| rule.label.is_some())
+    }
+}
+
+impl SensorThresholds {
+    fn matches(&self, node: &str, chip: &str, sensor: &str, label: Option<&str>) -> bool {
+        let nodes_match = self
+            .nodes
+            .as_ref()
+            .is_none_or(|patterns| patterns.iter().any(|pattern| glob_match(pattern, node)));
+        let label_match = match &self.label {
+            Some(pattern) => label.is_some_and(|label| glob_match(pattern, label)),
+            None => true,
+        };
+
+        nodes_match
+            && self.chip.as_ref().is_none_or(|pattern| glob_match(pattern, chip))
+            && self.sensor.as_ref().is_none_or(|pattern| glob_match(pattern, sensor))
+            && label_match
+    }
+}
+
+/// Matches `text` against a pattern where `*` matches any run of characters and `?` a single character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text position it currently absorbs up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, absorbed)) = backtrack {
+            p = star + 1;
+            t = absorbed + 1;
+            backtrack = Some((star, absorbed + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob_match("blade0*", "blade001"));
+        assert!(glob_match("blade00?", "blade001"));
+        assert!(glob_match("*nvme*", "nvme_nvme0"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("blade00?", "blade0011"));
+        assert!(!glob_match("blade1*", "blade001"));
+    }
+
+    #[test]
+    fn status_uses_inclusive_limits() {
+        let limits = Limits::new(Some(70.0), Some(85.0));
+
+        assert_eq!(limits.status(69.9), Status::Ok);
+        assert_eq!(limits.status(70.0), Status::Warning);
+        assert_eq!(limits.status(85.0), Status::Critical);
+        assert_eq!(limits.status(f64::NAN), Status::Unknown);
+        assert_eq!(Limits::default().status(50.0), Status::Unknown);
+    }
+}
@@ -0,0 +1,375 @@
+use crate::history::{fetch_history, HistoryParams, HistoryResponse};
+use crate::live::{self, LiveEvent, StatusChange};
+use crate::sensors::SensorMeasurement;
+use crate::state::AppState;
+use crate::thresholds::{glob_match, worst_judged, Status};
+use crate::{fetch_temperatures, Detail, QueryParams, TemperatureMeasurement, TemperatureResponse};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::Response;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Messages accepted from WebSocket clients.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    // Replaces the current subscription; the next delta contains every matching node
+    Subscribe(Subscription),
+    Snapshot {
+        id: Option<u64>,
+    },
+    History {
+        id: Option<u64>,
+        #[serde(flatten)]
+        params: HistoryParams,
+    },
+}
+
+/// Messages sent to WebSocket clients. Replies echo the `id` of their request.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    // Nodes that changed since the last delta, and nodes that no longer match the subscription
+    Delta {
+        updated: Vec<TemperatureMeasurement>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        removed: Vec<String>,
+    },
+    Status(StatusChange),
+    Snapshot {
+        id: Option<u64>,
+        data: TemperatureResponse,
+    },
+    History {
+        id: Option<u64>,
+        data: HistoryResponse,
+    },
+    Error {
+        id: Option<u64>,
+        message: String,
+    },
+}
+
+/// Which nodes, sensors and severities a client wants to hear about. Empty lists match everything.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Subscription {
+    // Node name patterns, * and ? supported
+    nodes: Vec<String>,
+    // Patterns matched against the sensor label, the sensor name or `chip/sensor`
+    sensors: Vec<String>,
+    severity: Vec<Status>,
+    // Set to `sensors` to include the per-sensor breakdown
+    detail: Option<Detail>,
+}
+
+impl Subscription {
+    fn matches_node(&self, node: &str) -> bool {
+        self.nodes.is_empty() || self.nodes.iter().any(|pattern| glob_match(pattern, node))
+    }
+
+    fn matches_severity(&self, status: Status) -> bool {
+        self.severity.is_empty() || self.severity.contains(&status)
+    }
+
+    fn matches_sensor(&self, sensor: &SensorMeasurement) -> bool {
+        let qualified = format!("{}/{}", sensor.chip, sensor.sensor);
+        self.sensors.iter().any(|pattern| {
+            glob_match(pattern, &sensor.sensor)
+                || glob_match(pattern, &qualified)
+                || sensor.label.as_deref().is_some_and(|label| glob_match(pattern, label))
+        })
+    }
+
+    /// The part of a snapshot this subscription covers.
+    fn view(&self, measurements: &[TemperatureMeasurement]) -> Vec<TemperatureMeasurement> {
+        measurements
+            .iter()
+            .filter(|measurement| self.matches_node(&measurement.node) && self.matches_severity(measurement.status))
+            .filter_map(|measurement| {
+                let sensors = if !self.sensors.is_empty() {
+                    let matching: Vec<SensorMeasurement> = measurement
+                        .sensors
+                        .iter()
+                        .flatten()
+                        .filter(|sensor| self.matches_sensor(sensor))
+                        .cloned()
+                        .collect();
+                    if matching.is_empty() {
+                        return None;
+                    }
+                    Some(matching)
+                } else if self.detail == Some(Detail::Sensors) {
+                    measurement.sensors.clone()
+                } else {
+                    None
+                };
+                Some(TemperatureMeasurement {
+                    sensors,
+                    ..measurement.clone()
+                })
+            })
+            .collect()
+    }
+
+    fn matches_change(&self, change: &StatusChange) -> bool {
+        self.matches_node(&change.node)
+            && (self.matches_severity(change.status) || change.previous_status.is_some_and(|s| self.matches_severity(s)))
+    }
+}
+
+/// Per-connection state: the subscription and what the client has already been sent.
+#[derive(Default)]
+struct Session {
+    subscription: Subscription,
+    sent: BTreeMap<String, TemperatureMeasurement>,
+    latest: Option<Arc<TemperatureResponse>>,
+}
+
+impl Session {
+    fn subscribe(&mut self, subscription: Subscription) -> Option<ServerMessage> {
+        self.subscription = subscription;
+        self.sent.clear();
+        self.delta()
+    }
+
+    fn update(&mut self, response: Arc<TemperatureResponse>) -> Option<ServerMessage> {
+        self.latest = Some(response);
+        self.delta()
+    }
+
+    // Changes between what the client has seen and the latest snapshot, None when nothing changed
+    fn delta(&mut self) -> Option<ServerMessage> {
+        let latest = self.latest.as_ref()?;
+        let view = self.subscription.view(&latest.measurements);
+        let current: BTreeSet<String> = view.iter().map(TemperatureMeasurement::key).collect();
+        let removed: Vec<String> = self.sent.keys().filter(|key| !current.contains(*key)).cloned().collect();
+        let updated: Vec<TemperatureMeasurement> = view
+            .into_iter()
+            .filter(|measurement| self.sent.get(&measurement.key()) != Some(measurement))
+            .collect();
+        if updated.is_empty() && removed.is_empty() {
+            return None;
+        }
+
+        for key in &removed {
+            self.sent.remove(key);
+        }
+        for measurement in &updated {
+            self.sent.insert(measurement.key(), measurement.clone());
+        }
+        Some(ServerMessage::Delta { updated, removed })
+    }
+
+    fn snapshot(&self, response: &TemperatureResponse) -> TemperatureResponse {
+        let measurements = self.subscription.view(&response.measurements);
+        TemperatureResponse {
+            status: worst_judged(measurements.iter().map(|m| m.status)),
+            measurements,
+            stale: response.stale,
+            data_age_seconds: response.data_age_seconds,
+            errors: response.errors.clone(),
+            warnings: response.warnings.clone(),
+            cluster_errors: response.cluster_errors.clone(),
+        }
+    }
+}
+
+pub async fn temperatures_socket(State(state): State<AppState>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(move |socket| serve(socket, state))
+}
+
+async fn serve(mut socket: WebSocket, state: AppState) {
+    let (current, receiver) = state.hub.subscribe();
+    let mut events = std::pin::pin!(live::events(receiver));
+    let mut session = Session::default();
+
+    if let Some(response) = current {
+        if let Some(message) = session.update(response) {
+            if send(&mut socket, &message).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    loop {
+        let reply = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => handle(&state, &mut session, &text).await,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // Pings are answered by axum, binary frames are not part of the protocol
+                Some(Ok(_)) => None,
+            },
+            event = events.next() => match event {
+                Some(LiveEvent::Snapshot(response)) => session.update(response),
+                Some(LiveEvent::Status(change)) => {
+                    session.subscription.matches_change(&change).then_some(ServerMessage::Status(change))
+                }
+                None => break,
+            },
+        };
+        if let Some(message) = reply {
+            if send(&mut socket, &message).await.is_err() {
+                break;
+            }
+        }
+    }
+    debug!("WebSocket client disconnected");
+}
+
+async fn send(socket: &mut WebSocket, message: &ServerMessage) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(message).expect("server messages serialize to JSON");
+    socket.send(Message::Text(text)).await
+}
+
+async fn handle(state: &AppState, session: &mut Session, text: &str) -> Option<ServerMessage> {
+    let message = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => message,
+        Err(e) => {
+            return Some(ServerMessage::Error {
+                id: None,
+                message: format!("invalid message: {}", e),
+            })
+        }
+    };
+
+    match message {
+        ClientMessage::Subscribe(subscription) => session.subscribe(subscription),
+        ClientMessage::Snapshot { id } => Some(match &session.latest {
+            Some(response) => ServerMessage::Snapshot {
+                id,
+                data: session.snapshot(response),
+            },
+            // The poller has not produced a snapshot yet, ask the upstream directly
+            None => {
+                let params = QueryParams {
+                    detail: Some(Detail::Sensors),
+                    ..QueryParams::default()
+                };
+                match tokio::time::timeout(state.request_deadline, fetch_temperatures(state, &params)).await {
+                    Ok(Ok(response)) => ServerMessage::Snapshot {
+                        id,
+                        data: session.snapshot(&response),
+                    },
+                    Ok(Err(e)) => error(id, format!("fetching temperatures failed: {}", e)),
+                    Err(_) => error(id, "fetching temperatures timed out".to_string()),
+                }
+            }
+        }),
+        ClientMessage::History { id, params } => {
+            Some(match tokio::time::timeout(state.request_deadline, fetch_history(state, &params)).await {
+                Ok(Ok(data)) => ServerMessage::History { id, data },
+                Ok(Err(e)) => error(id, format!("history request failed: {}", e)),
+                Err(_) => error(id, "history request timed out".to_string()),
+            })
+        }
+    }
+}
+
+fn error(id: Option<u64>, message: String) -> ServerMessage {
+    warn!("WebSocket request failed: {}", message);
+    ServerMessage::Error { id, message }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::thresholds::Limits;
+
+    fn measurement(node: &str, temperature: f64, status: Status) -> TemperatureMeasurement {
+        TemperatureMeasurement {
+            node: node.to_string(),
+            cluster: None,
+            minutely_temperature: Some(temperature),
+            hourly_temperature: Some(temperature),
+            daily_temperature: Some(temperature),
+            missing: Default::default(),
+            last_seen: None,
+            status,
+            windows: None,
+            sensors: Some(vec![SensorMeasurement {
+                instance: "10.0.0.1:9100".to_string(),
+                chip: "platform_coretemp_0".to_string(),
+                chip_name: None,
+                sensor: "temp1".to_string(),
+                label: Some("Package id 0".to_string()),
+                minutely_temperature: Some(temperature),
+                hourly_temperature: Some(temperature),
+                daily_temperature: Some(temperature),
+                missing: Default::default(),
+                windows: None,
+                status,
+                thresholds: Limits::default(),
+                headroom: None,
+            }]),
+        }
+    }
+
+    fn snapshot(measurements: Vec<TemperatureMeasurement>) -> Arc<TemperatureResponse> {
+        Arc::new(TemperatureResponse {
+            status: worst_judged(measurements.iter().map(|m| m.status)),
+            measurements,
+            ..TemperatureResponse::default()
+        })
+    }
+
+    fn delta(message: Option<ServerMessage>) -> (Vec<String>, Vec<String>) {
+        match message {
+            Some(ServerMessage::Delta { updated, removed }) => (updated.into_iter().map(|m| m.node).collect(), removed),
+            None => (Vec::new(), Vec::new()),
+            Some(other) => panic!("expected a delta, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn deltas_only_contain_changed_nodes() {
+        let mut session = Session::default();
+        let first = snapshot(vec![measurement("blade001", 60.0, Status::Ok), measurement("blade002", 61.0, Status::Ok)]);
+        assert_eq!(delta(session.update(first)).0, vec!["blade001", "blade002"]);
+
+        let second = snapshot(vec![measurement("blade001", 60.0, Status::Ok), measurement("blade002", 65.0, Status::Ok)]);
+        assert_eq!(delta(session.update(second.clone())).0, vec!["blade002"]);
+        assert!(session.update(second).is_none());
+    }
+
+    #[test]
+    fn subscription_filters_nodes_severity_and_sensors() {
+        let mut session = Session::default();
+        session.update(snapshot(vec![
+            measurement("blade001", 80.0, Status::Warning),
+            measurement("blade002", 60.0, Status::Ok),
+            measurement("gpu001", 82.0, Status::Warning),
+        ]));
+
+        let subscription: Subscription =
+            serde_json::from_str(r#"{"nodes": ["blade*"], "sensors": ["Package*"], "severity": ["warning", "critical"]}"#)
+                .unwrap();
+        match session.subscribe(subscription) {
+            Some(ServerMessage::Delta { updated, .. }) => {
+                assert_eq!(updated.len(), 1);
+                assert_eq!(updated[0].node, "blade001");
+                assert_eq!(updated[0].sensors.as_ref().unwrap()[0].sensor, "temp1");
+            }
+            other => panic!("expected a delta, got {:?}", other),
+        }
+
+        // Cooling down below warning drops the node from the subscription
+        let (updated, removed) = delta(session.update(snapshot(vec![measurement("blade001", 60.0, Status::Ok)])));
+        assert!(updated.is_empty());
+        assert_eq!(removed, vec!["blade001"]);
+    }
+
+    #[test]
+    fn client_messages_parse() {
+        let history: ClientMessage =
+            serde_json::from_str(r#"{"type": "history", "id": 7, "node": "blade001", "step": "5m"}"#).unwrap();
+        assert!(matches!(history, ClientMessage::History { id: Some(7), .. }));
+
+        let snapshot: ClientMessage = serde_json::from_str(r#"{"type": "snapshot"}"#).unwrap();
+        assert!(matches!(snapshot, ClientMessage::Snapshot { id: None }));
+    }
+}
@@ -0,0 +1,199 @@
+use crate::error::ApiError;
+use crate::config::parse_duration;
+use crate::promql::{Expr, RangeFunction, Selector};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Upper bound for ?windows= so a single request cannot fan out into dozens of upstream queries
+const MAX_WINDOWS: usize = 8;
+const MAX_STATS: usize = 4;
+
+/// Aggregated values per window and statistic, e.g. `{"15m": {"max": 71.5, "p95": 70.2}}`.
+pub type WindowValues = BTreeMap<String, BTreeMap<String, f64>>;
+
+/// Statistic computed over a window with the matching `*_over_time` function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stat {
+    Max,
+    Min,
+    Avg,
+    // Percentile between 1 and 99, computed with quantile_over_time
+    Percentile(u8),
+}
+
+impl Stat {
+    /// The range function for this statistic over `window` of `selector`.
+    pub fn over_time(&self, selector: Selector, window: Duration) -> Expr {
+        let function = match self {
+            Stat::Max => RangeFunction::Max,
+            Stat::Min => RangeFunction::Min,
+            Stat::Avg => RangeFunction::Avg,
+            Stat::Percentile(p) => RangeFunction::Quantile(*p as f64 / 100.0),
+        };
+        function.apply(selector.range(window))
+    }
+}
+
+impl FromStr for Stat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "max" => Ok(Stat::Max),
+            "min" => Ok(Stat::Min),
+            "avg" => Ok(Stat::Avg),
+            _ => match value.strip_prefix('p').and_then(|p| p.parse::<u8>().ok()) {
+                Some(p) if (1..=99).contains(&p) => Ok(Stat::Percentile(p)),
+                _ => Err(format!("unknown statistic {:?}, expected max, min, avg or p1..p99", value)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Stat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stat::Max => write!(f, "max"),
+            Stat::Min => write!(f, "min"),
+            Stat::Avg => write!(f, "avg"),
+            Stat::Percentile(p) => write!(f, "p{}", p),
+        }
+    }
+}
+
+/// One requested (window, statistic) combination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowStat {
+    // As requested, used as the key in `windows`
+    pub window: String,
+    pub duration: Duration,
+    pub stat: Stat,
+}
+
+/// A window whose query failed; its values are null (or missing from `windows`) in the response.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WindowError {
+    // Federated cluster the window failed for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<String>,
+    pub window: String,
+    // Statistic of a ?windows= entry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stat: Option<String>,
+    // Prometheus errorType, `timeout` when the window deadline passed or `unavailable` when the upstream failed
+    pub error_type: String,
+    pub error: String,
+}
+
+impl WindowError {
+    pub fn new(window: &str, stat: Option<String>, error: &anyhow::Error) -> Self {
+        let error_type = match ApiError::upstream(error) {
+            ApiError::Query(query_error) => query_error.error_type,
+            ApiError::UpstreamTimeout(_) => "timeout".to_string(),
+            _ => "unavailable".to_string(),
+        };
+        Self {
+            cluster: None,
+            window: window.to_string(),
+            stat,
+            error_type,
+            error: format!("{:#}", error),
+        }
+    }
+}
+
+/// Why a node or sensor has no value for a window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingReason {
+    // The window's query failed, see the response `errors`
+    QueryFailed,
+    // The query succeeded without samples for it, e.g. a blade that just booted or went offline
+    NoData,
+}
+
+/// Value of one window, or why there is none.
+pub type Reading = Result<f64, MissingReason>;
+
+/// Reasons for null temperatures, keyed by `minutely`, `hourly` or `daily`.
+pub type MissingWindows = BTreeMap<String, MissingReason>;
+
+/// Hottest of the readings; without any value the first reason is kept.
+pub fn max_reading(readings: impl IntoIterator<Item = Reading>) -> Reading {
+    readings
+        .into_iter()
+        .reduce(|a, b| match (a, b) {
+            (Ok(a), Ok(b)) => Ok(a.max(b)),
+            (Ok(value), Err(_)) | (Err(_), Ok(value)) => Ok(value),
+            (Err(reason), Err(_)) => Err(reason),
+        })
+        .unwrap_or(Err(MissingReason::NoData))
+}
+
+/// Collects the reasons of the missing readings.
+pub fn missing_windows<'a>(readings: impl IntoIterator<Item = (&'a str, Reading)>) -> MissingWindows {
+    readings
+        .into_iter()
+        .filter_map(|(name, reading)| Some((name.to_string(), reading.err()?)))
+        .collect()
+}
+
+/// Parses `?windows=5m,1h&stat=max,p95` into every window/statistic combination.
+/// Statistics default to `max` when only windows are given.
+pub fn parse_window_stats(windows: &str, stats: Option<&str>, max_window: Duration) -> Result<Vec<WindowStat>, String> {
+    let windows = split_list(windows);
+    if windows.is_empty() || windows.len() > MAX_WINDOWS {
+        return Err(format!("between 1 and {} windows must be requested", MAX_WINDOWS));
+    }
+    let mut durations = Vec::with_capacity(windows.len());
+    for window in &windows {
+        let duration = parse_duration(window).map_err(|e| format!("invalid window {:?}: {}", window, e))?;
+        if duration > max_window {
+            return Err(format!("window {} exceeds the maximum of {:?}", window, max_window));
+        }
+        durations.push(duration);
+    }
+
+    let stats = match stats {
+        Some(stats) => split_list(stats)
+            .into_iter()
+            .map(|stat| stat.parse())
+            .collect::<Result<Vec<Stat>, _>>()?,
+        None => vec![Stat::Max],
+    };
+    if stats.is_empty() || stats.len() > MAX_STATS {
+        return Err(format!("between 1 and {} statistics must be requested", MAX_STATS));
+    }
+
+    Ok(windows
+        .iter()
+        .zip(durations)
+        .flat_map(|(window, duration)| {
+            stats.iter().map(move |&stat| WindowStat {
+                window: window.to_string(),
+                duration,
+                stat,
+            })
+        })
+        .collect())
+}
+
+// Items in request order, repeated ones only once
+fn split_list(value: &str) -> Vec<&str> {
+    let mut seen = HashSet::new();
+    value.split(',').map(str::trim).filter(|item| !item.is_empty() && seen.insert(*item)).collect()
+}
+
+/// Merges `other` into `into`, keeping the hottest value of every window/statistic.
+pub fn merge_max(into: &mut WindowValues, other: &WindowValues) {
+    for (window, stats) in other {
+        let target = into.entry(window.clone()).or_default();
+        for (stat, &value) in stats {
+            let current = target.entry(stat.clone()).or_insert(f64::NEG_INFINITY);
+            *current = current.max(value);
+        }
+    }
+}